@@ -0,0 +1,146 @@
+use crate::config::Overrides;
+use crate::output::Format;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+/// Collects host metrics into libsql.
+#[derive(Parser)]
+#[command(name = "tcl", version)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+    /// Defaults to `collect`.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Flags accepted by every subcommand.
+#[derive(Args)]
+pub struct GlobalArgs {
+    /// Config file [default: $TCL_CONFIG, else ./tcl.toml if it exists]
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Database URL, overriding the config file and LIBSQL_URL
+    #[arg(long, global = true, value_name = "URL")]
+    pub database_url: Option<String>,
+    /// Output format for commands that print results
+    #[arg(long, global = true, value_enum)]
+    pub format: Option<Format>,
+    /// Log filter such as `info` or `tcl=debug`, overriding TCL_LOG
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log_level: Option<String>,
+    /// `text` or `json`, overriding TCL_LOG_FORMAT
+    #[arg(long, global = true, value_name = "FORMAT")]
+    pub log_format: Option<String>,
+    /// Same as the `daemon` subcommand, for scripts that predate subcommands
+    #[arg(long, global = true, hide = true)]
+    pub daemon: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Collect one snapshot and record it
+    Collect,
+    /// Collect on an interval until SIGINT or SIGTERM
+    Daemon {
+        /// Seconds between collections, overriding the config file and TCL_INTERVAL
+        #[arg(long, value_name = "SECS")]
+        interval: Option<u64>,
+        /// Serve Prometheus metrics at http://ADDR/metrics, overriding the
+        /// config file and TCL_METRICS_LISTEN
+        #[arg(long, value_name = "ADDR")]
+        metrics_listen: Option<String>,
+    },
+    /// Bring the database schema up to date
+    Migrate,
+    /// Read back recorded history
+    Query(QueryArgs),
+    /// Write recorded rows of one table as CSV, JSON, JSON Lines or Parquet
+    Export(ExportArgs),
+    /// Check the configuration, state directory and database
+    Doctor,
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+/// Without `--metric`, lists the hosts that reported within the window.
+/// Otherwise prints each metric's latest value per host, or with `--stats` or
+/// `--bucket` its minimum, maximum and average.
+#[derive(Args)]
+pub struct QueryArgs {
+    /// Metric to read, or a glob such as `disk.*`; may be repeated
+    #[arg(long = "metric", short, value_name = "METRIC")]
+    pub metrics: Vec<String>,
+    #[command(flatten)]
+    pub window: WindowArgs,
+    /// Minimum, maximum and average over the whole window
+    #[arg(long, conflicts_with = "bucket")]
+    pub stats: bool,
+    /// Minimum, maximum and average per bucket of this length, e.g. `5m`
+    #[arg(long, value_name = "DURATION")]
+    pub bucket: Option<String>,
+    /// List the metrics that can be queried
+    #[arg(long, exclusive = true)]
+    pub list_metrics: bool,
+}
+
+/// Rows are written oldest first and read from the database in pages, so
+/// large tables are never held in memory. The format defaults to the output
+/// file's extension, else CSV.
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Table to export: runs, disks, cpus, cpu_cores, memory,
+    /// network_interfaces, processes or sensors
+    #[arg(long, default_value = "disks", value_name = "TABLE")]
+    pub table: String,
+    /// File to write instead of stdout
+    #[arg(long, short, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+    #[command(flatten)]
+    pub window: WindowArgs,
+}
+
+/// The hosts and time range a command reads.
+#[derive(Args)]
+pub struct WindowArgs {
+    /// Host name or machine id to read; may be repeated [default: all hosts]
+    #[arg(long = "host", value_name = "HOST")]
+    pub hosts: Vec<String>,
+    /// Start of the window: a UTC time such as `2024-06-01T12:00`, or a
+    /// duration ago such as `6h`
+    #[arg(long, value_name = "TIME")]
+    pub since: Option<String>,
+    /// End of the window, in the same forms as --since [default: now]
+    #[arg(long, value_name = "TIME")]
+    pub until: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Check every setting and report errors with where they were set
+    Validate,
+}
+
+impl Cli {
+    /// The settings given on the command line, for [`crate::config::Config::load`].
+    pub fn overrides(&self) -> Overrides {
+        let (interval, metrics_listen) = match &self.command {
+            Some(Command::Daemon {
+                interval,
+                metrics_listen,
+            }) => (*interval, metrics_listen.clone()),
+            _ => (None, None),
+        };
+        Overrides {
+            config: self.global.config.clone(),
+            database_url: self.global.database_url.clone(),
+            interval,
+            metrics_listen,
+            log_level: self.global.log_level.clone(),
+            log_format: self.global.log_format.clone(),
+        }
+    }
+}
@@ -0,0 +1,1026 @@
+use crate::daemon::DaemonOptions;
+use crate::db::DbTarget;
+use crate::disk::DiskSelection;
+use crate::logging;
+use crate::network::InterfaceFilter;
+use crate::process::ProcessOptions;
+use crate::sink::SinkTarget;
+use crate::state::default_state_dir;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml::Spanned;
+
+/// Read from the working directory when neither `--config` nor `TCL_CONFIG` is given.
+const DEFAULT_PATH: &str = "tcl.toml";
+
+/// Where a setting was taken from, so an error can point at it.
+#[derive(Clone, Debug)]
+pub enum Origin {
+    Default,
+    File {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+    },
+    Env(&'static str),
+    Flag(&'static str),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File { path, line, column } => {
+                write!(f, "{}:{}:{}", path.display(), line, column)
+            }
+            Origin::Env(var) => write!(f, "{}", var),
+            Origin::Flag(flag) => write!(f, "{}", flag),
+        }
+    }
+}
+
+/// A setting that needs checking beyond its type, kept with its [`Origin`].
+#[derive(Clone, Debug)]
+pub struct Value<T> {
+    pub value: T,
+    pub origin: Origin,
+}
+
+impl<T> Value<T> {
+    fn default(value: T) -> Self {
+        Value {
+            value,
+            origin: Origin::Default,
+        }
+    }
+
+    fn env(value: T, var: &'static str) -> Self {
+        Value {
+            value,
+            origin: Origin::Env(var),
+        }
+    }
+
+    /// An error about this setting, located where it was set.
+    pub fn error(&self, message: impl Into<String>) -> ConfigError {
+        ConfigError {
+            origin: self.origin.clone(),
+            message: message.into(),
+        }
+    }
+}
+
+/// An invalid setting, or a config file that could not be read or parsed.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub origin: Origin,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.origin {
+            Origin::Default => write!(f, "{}", self.message),
+            _ => write!(f, "{}: {}", self.origin, self.message),
+        }
+    }
+}
+
+/// Settings given on the command line, which take precedence over everything else.
+#[derive(Default)]
+pub struct Overrides {
+    pub config: Option<PathBuf>,
+    pub database_url: Option<String>,
+    pub interval: Option<u64>,
+    pub metrics_listen: Option<String>,
+    pub log_level: Option<String>,
+    pub log_format: Option<String>,
+}
+
+/// The agent's settings after layering, lowest precedence first: built-in
+/// defaults, the TOML config file, `.env`, the process environment and
+/// command-line flags.
+///
+/// `.env` is loaded into the environment without replacing variables that are
+/// already set, which is what puts it below the real environment. Settings that
+/// can only be checked once the whole file is known (rules, globs, modes) keep
+/// their [`Origin`] and are validated by the `from_config` constructors of the
+/// modules that use them; see [`Config::validate`].
+pub struct Config {
+    /// The config file that was read, if any.
+    pub path: Option<PathBuf>,
+    pub state_dir: PathBuf,
+    /// Free-form key/value pairs stored with the host, e.g. `env = "prod"`.
+    pub labels: BTreeMap<String, String>,
+    pub database: DatabaseConfig,
+    pub collectors: CollectorsConfig,
+    pub disks: FilterConfig,
+    pub networks: FilterConfig,
+    pub processes: ProcessesConfig,
+    pub daemon: DaemonConfig,
+    pub spool: SpoolConfig,
+    pub log: LogConfig,
+    pub sinks: Vec<SinkConfig>,
+}
+
+pub struct DatabaseConfig {
+    pub url: Option<String>,
+    pub auth_token: String,
+    pub mode: Option<Value<String>>,
+    pub replica_path: Option<PathBuf>,
+    /// Seconds between embedded replica syncs.
+    pub sync_interval: Value<u64>,
+}
+
+/// Which collectors run each cycle. Host identity is always collected.
+#[derive(Clone, Copy)]
+pub struct CollectorsConfig {
+    pub cpu: bool,
+    pub memory: bool,
+    pub disks: bool,
+    pub networks: bool,
+    pub sensors: bool,
+    pub processes: bool,
+}
+
+impl CollectorsConfig {
+    const NAMES: [&'static str; 6] = ["cpu", "memory", "disks", "networks", "sensors", "processes"];
+
+    fn set(&mut self, name: &str, enabled: bool) -> bool {
+        let flag = match name {
+            "cpu" => &mut self.cpu,
+            "memory" => &mut self.memory,
+            "disks" => &mut self.disks,
+            "networks" => &mut self.networks,
+            "sensors" => &mut self.sensors,
+            "processes" => &mut self.processes,
+            _ => return false,
+        };
+        *flag = enabled;
+        true
+    }
+}
+
+/// Include and exclude lists, for disk rules or interface globs.
+#[derive(Default)]
+pub struct FilterConfig {
+    pub include: Vec<Value<String>>,
+    pub exclude: Vec<Value<String>>,
+}
+
+pub struct ProcessesConfig {
+    pub top: Value<usize>,
+    pub sort: Vec<Value<String>>,
+    pub redact_cmdline: bool,
+}
+
+pub struct DaemonConfig {
+    /// Seconds between collections.
+    pub interval: Value<u64>,
+    /// Upper bound in seconds of the random delay added to each collection.
+    pub jitter: u64,
+    /// Address such as `127.0.0.1:9464` to serve Prometheus metrics on.
+    pub metrics_listen: Option<Value<String>>,
+}
+
+pub struct SpoolConfig {
+    /// Defaults to `spool` in the state directory.
+    pub dir: Option<PathBuf>,
+    pub max_bytes: u64,
+}
+
+pub struct LogConfig {
+    /// An `EnvFilter` directive such as `info` or `tcl=debug`.
+    pub level: Value<String>,
+    /// `text` or `json`.
+    pub format: Value<String>,
+}
+
+/// Per-sink environment variables, `TCL_SINK_<TYPE>_<SETTING>`.
+const SINK_VARS: &[&str] = &[
+    "TCL_SINK_LIBSQL_TIMEOUT",
+    "TCL_SINK_STDOUT_FORMAT",
+    "TCL_SINK_STDOUT_TIMEOUT",
+    "TCL_SINK_FILE_PATH",
+    "TCL_SINK_FILE_MAX_BYTES",
+    "TCL_SINK_FILE_KEEP",
+    "TCL_SINK_FILE_FORMAT",
+    "TCL_SINK_FILE_TIMEOUT",
+    "TCL_SINK_HTTP_URL",
+    "TCL_SINK_HTTP_HEADERS",
+    "TCL_SINK_HTTP_TIMEOUT",
+    "TCL_SINK_HTTP_FORMAT",
+    "TCL_SINK_TCP_ADDRESS",
+    "TCL_SINK_TCP_TIMEOUT",
+    "TCL_SINK_TCP_FORMAT",
+    "TCL_SINK_UDP_ADDRESS",
+    "TCL_SINK_UDP_FORMAT",
+    "TCL_SINK_UDP_TIMEOUT",
+    "TCL_SINK_OTLP_URL",
+    "TCL_SINK_OTLP_PROTOCOL",
+    "TCL_SINK_OTLP_HEADERS",
+    "TCL_SINK_OTLP_TIMEOUT",
+];
+
+/// A destination for snapshots. Each setting besides `type` applies to some
+/// kinds of sink and is ignored by the others; see [`SinkTarget`].
+pub struct SinkConfig {
+    /// `libsql`, `stdout`, `file`, `http`, `tcp`, `udp` or `otlp`.
+    pub kind: Value<String>,
+    /// `json`, `influx` or `graphite`, for the `stdout`, `file`, `http`, `tcp`
+    /// and `udp` sinks.
+    pub format: Value<String>,
+    /// The file a `file` sink appends to.
+    pub path: Option<PathBuf>,
+    /// Size in bytes at which a `file` sink rotates its file; 0 never rotates.
+    pub max_bytes: u64,
+    /// How many rotated files a `file` sink keeps.
+    pub keep: usize,
+    /// The endpoint an `http` or `otlp` sink sends to.
+    pub url: Option<Value<String>>,
+    /// Extra request headers for an `http` or `otlp` sink, e.g. `Authorization`.
+    pub headers: BTreeMap<String, Value<String>>,
+    /// `grpc` or `http/protobuf`, for an `otlp` sink.
+    pub protocol: Value<String>,
+    /// `host:port` a `tcp` or `udp` sink sends to.
+    pub address: Option<Value<String>>,
+    /// Seconds any sink may spend on one write or sync before it is abandoned
+    /// and reported as failed.
+    pub timeout: Value<u64>,
+}
+
+impl SinkConfig {
+    fn new(kind: Value<String>) -> Self {
+        SinkConfig {
+            kind,
+            format: Value::default("json".to_string()),
+            path: None,
+            max_bytes: 64 * 1024 * 1024,
+            keep: 5,
+            url: None,
+            headers: BTreeMap::new(),
+            protocol: Value::default("grpc".to_string()),
+            address: None,
+            timeout: Value::default(10),
+        }
+    }
+
+    /// Applies the [`SINK_VARS`] for this sink's type.
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        let prefix = format!("TCL_SINK_{}_", self.kind.value.to_uppercase());
+        let var = |setting: &str| {
+            SINK_VARS
+                .iter()
+                .copied()
+                .find(|var| var.strip_prefix(prefix.as_str()) == Some(setting))
+        };
+
+        if let Some(key) = var("FORMAT") {
+            if let Some(format) = env_var(key) {
+                self.format = Value::env(format, key);
+            }
+        }
+        if let Some(path) = var("PATH").and_then(env_var) {
+            self.path = Some(PathBuf::from(path));
+        }
+        if let Some(max_bytes) = var("MAX_BYTES").map(env_parse).transpose()?.flatten() {
+            self.max_bytes = max_bytes;
+        }
+        if let Some(keep) = var("KEEP").map(env_parse).transpose()?.flatten() {
+            self.keep = keep;
+        }
+        if let Some(key) = var("URL") {
+            if let Some(url) = env_var(key) {
+                self.url = Some(Value::env(url, key));
+            }
+        }
+        if let Some(key) = var("HEADERS") {
+            if let Some(headers) = env_pairs(key)? {
+                self.headers.extend(
+                    headers
+                        .into_iter()
+                        .map(|(name, value)| (name, Value::env(value, key))),
+                );
+            }
+        }
+        if let Some(key) = var("PROTOCOL") {
+            if let Some(protocol) = env_var(key) {
+                self.protocol = Value::env(protocol, key);
+            }
+        }
+        if let Some(key) = var("ADDRESS") {
+            if let Some(address) = env_var(key) {
+                self.address = Some(Value::env(address, key));
+            }
+        }
+        if let Some(key) = var("TIMEOUT") {
+            if let Some(secs) = env_parse(key)? {
+                self.timeout = Value::env(secs, key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    fn defaults() -> Self {
+        Config {
+            path: None,
+            state_dir: default_state_dir(),
+            labels: BTreeMap::new(),
+            database: DatabaseConfig {
+                url: None,
+                auth_token: String::new(),
+                mode: None,
+                replica_path: None,
+                sync_interval: Value::default(60),
+            },
+            collectors: CollectorsConfig {
+                cpu: true,
+                memory: true,
+                disks: true,
+                networks: true,
+                sensors: true,
+                processes: false,
+            },
+            disks: FilterConfig::default(),
+            networks: FilterConfig::default(),
+            processes: ProcessesConfig {
+                top: Value::default(10),
+                sort: vec![
+                    Value::default("cpu".to_string()),
+                    Value::default("memory".to_string()),
+                ],
+                redact_cmdline: false,
+            },
+            daemon: DaemonConfig {
+                interval: Value::default(60),
+                jitter: 0,
+                metrics_listen: None,
+            },
+            spool: SpoolConfig {
+                dir: None,
+                max_bytes: 64 * 1024 * 1024,
+            },
+            log: LogConfig {
+                level: Value::default("info".to_string()),
+                format: Value::default("text".to_string()),
+            },
+            sinks: vec![SinkConfig::new(Value::default("libsql".to_string()))],
+        }
+    }
+
+    /// Resolves the settings. The file is `--config`, else `TCL_CONFIG`, else
+    /// `tcl.toml` in the working directory if it exists; an explicitly named
+    /// file must exist.
+    pub fn load(overrides: &Overrides) -> Result<Self, ConfigError> {
+        let mut config = Config::defaults();
+
+        let explicit = match (&overrides.config, env::var_os("TCL_CONFIG")) {
+            (Some(path), _) => Some(Value {
+                value: path.clone(),
+                origin: Origin::Flag("--config"),
+            }),
+            (None, Some(path)) => Some(Value::env(PathBuf::from(path), "TCL_CONFIG")),
+            (None, None) => None,
+        };
+        match explicit {
+            Some(path) => {
+                let text = fs::read_to_string(&path.value).map_err(|e| {
+                    path.error(format!("could not read {}: {}", path.value.display(), e))
+                })?;
+                config.apply_file(&path.value, &text)?;
+            }
+            None if Path::new(DEFAULT_PATH).is_file() => {
+                let path = PathBuf::from(DEFAULT_PATH);
+                let text = fs::read_to_string(&path).map_err(|e| ConfigError {
+                    origin: Origin::Default,
+                    message: format!("could not read {}: {}", DEFAULT_PATH, e),
+                })?;
+                config.apply_file(&path, &text)?;
+            }
+            None => {}
+        }
+
+        config.apply_env()?;
+        config.apply_overrides(overrides);
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, path: &Path, text: &str) -> Result<(), ConfigError> {
+        let source = Source { path, text };
+        let file: File = toml::from_str(text).map_err(|e| ConfigError {
+            origin: source.origin(e.span().unwrap_or(0..0)),
+            message: e.message().trim_end().to_string(),
+        })?;
+        self.path = Some(path.to_path_buf());
+
+        if let Some(dir) = file.state_dir {
+            self.state_dir = dir;
+        }
+        self.labels.extend(file.labels);
+
+        let database = file.database;
+        if database.url.is_some() {
+            self.database.url = database.url;
+        }
+        if let Some(token) = database.auth_token {
+            self.database.auth_token = token;
+        }
+        if let Some(mode) = database.mode {
+            self.database.mode = Some(source.value(mode));
+        }
+        if database.replica_path.is_some() {
+            self.database.replica_path = database.replica_path;
+        }
+        if let Some(secs) = database.sync_interval {
+            self.database.sync_interval = source.value(secs);
+        }
+
+        let collectors = file.collectors;
+        for (name, enabled) in CollectorsConfig::NAMES.into_iter().zip([
+            collectors.cpu,
+            collectors.memory,
+            collectors.disks,
+            collectors.networks,
+            collectors.sensors,
+            collectors.processes,
+        ]) {
+            if let Some(enabled) = enabled {
+                self.collectors.set(name, enabled);
+            }
+        }
+
+        source.apply_filter(&mut self.disks, file.disks);
+        source.apply_filter(&mut self.networks, file.networks);
+
+        let processes = file.processes;
+        if let Some(top) = processes.top {
+            self.processes.top = source.value(top);
+        }
+        if let Some(sort) = processes.sort {
+            self.processes.sort = sort.into_iter().map(|s| source.value(s)).collect();
+        }
+        if let Some(redact) = processes.redact_cmdline {
+            self.processes.redact_cmdline = redact;
+        }
+
+        if let Some(secs) = file.daemon.interval {
+            self.daemon.interval = source.value(secs);
+        }
+        if let Some(secs) = file.daemon.jitter {
+            self.daemon.jitter = secs;
+        }
+        if let Some(listen) = file.daemon.metrics_listen {
+            self.daemon.metrics_listen = Some(source.value(listen));
+        }
+
+        if file.spool.dir.is_some() {
+            self.spool.dir = file.spool.dir;
+        }
+        if let Some(max_bytes) = file.spool.max_bytes {
+            self.spool.max_bytes = max_bytes;
+        }
+
+        if let Some(level) = file.log.level {
+            self.log.level = source.value(level);
+        }
+        if let Some(format) = file.log.format {
+            self.log.format = source.value(format);
+        }
+
+        if let Some(sinks) = file.sinks {
+            self.sinks = sinks
+                .into_iter()
+                .map(|file| {
+                    let mut sink = SinkConfig::new(source.value(file.kind));
+                    if let Some(format) = file.format {
+                        sink.format = source.value(format);
+                    }
+                    sink.path = file.path;
+                    if let Some(max_bytes) = file.max_bytes {
+                        sink.max_bytes = max_bytes;
+                    }
+                    if let Some(keep) = file.keep {
+                        sink.keep = keep;
+                    }
+                    sink.url = file.url.map(|url| source.value(url));
+                    sink.headers = file
+                        .headers
+                        .into_iter()
+                        .map(|(name, value)| (name, source.value(value)))
+                        .collect();
+                    if let Some(protocol) = file.protocol {
+                        sink.protocol = source.value(protocol);
+                    }
+                    sink.address = file.address.map(|address| source.value(address));
+                    if let Some(secs) = file.timeout {
+                        sink.timeout = source.value(secs);
+                    }
+                    sink
+                })
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Applies the environment variables the agent has always read, plus
+    /// `TCL_COLLECTORS` (the complete list of collectors to run), `TCL_LABELS`
+    /// (comma-separated `key=value` pairs) and `TCL_SINKS` (the complete list of
+    /// sink types). The [`SINK_VARS`] apply to every sink of their type.
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(dir) = env_var("TCL_STATE_DIR") {
+            self.state_dir = PathBuf::from(dir);
+        }
+        if let Some(labels) = env_pairs("TCL_LABELS")? {
+            self.labels.extend(labels);
+        }
+
+        if let Some(url) = env_var("LIBSQL_URL") {
+            self.database.url = Some(url);
+        }
+        if let Some(token) = env_var("LIBSQL_AUTH_TOKEN") {
+            self.database.auth_token = token;
+        }
+        if let Some(mode) = env_var("LIBSQL_MODE") {
+            self.database.mode = Some(Value::env(mode, "LIBSQL_MODE"));
+        }
+        if let Some(path) = env_var("LIBSQL_REPLICA_PATH") {
+            self.database.replica_path = Some(PathBuf::from(path));
+        }
+        if let Some(secs) = env_parse("LIBSQL_SYNC_INTERVAL")? {
+            self.database.sync_interval = Value::env(secs, "LIBSQL_SYNC_INTERVAL");
+        }
+
+        if let Some(names) = env_list("TCL_COLLECTORS") {
+            for name in CollectorsConfig::NAMES {
+                self.collectors.set(name, false);
+            }
+            for name in names {
+                if !self.collectors.set(&name.value, true) {
+                    return Err(name.error(format!(
+                        "unknown collector `{}` (expected {})",
+                        name.value,
+                        CollectorsConfig::NAMES.join(", ")
+                    )));
+                }
+            }
+        }
+
+        if let Some(include) = env_list("TCL_DISK_INCLUDE") {
+            self.disks.include = include;
+        }
+        if let Some(exclude) = env_list("TCL_DISK_EXCLUDE") {
+            self.disks.exclude = exclude;
+        }
+        if let Some(include) = env_list("TCL_NETWORK_INCLUDE") {
+            self.networks.include = include;
+        }
+        if let Some(exclude) = env_list("TCL_NETWORK_EXCLUDE") {
+            self.networks.exclude = exclude;
+        }
+
+        // A top count in the environment turns the process collector on, and
+        // zero turns it off.
+        if let Some(top) = env_parse::<usize>("TCL_PROCESSES_TOP")? {
+            self.collectors.processes = top > 0;
+            if top > 0 {
+                self.processes.top = Value::env(top, "TCL_PROCESSES_TOP");
+            }
+        }
+        if let Some(sort) = env_list("TCL_PROCESSES_SORT") {
+            self.processes.sort = sort;
+        }
+        if let Some(redact) = env_var("TCL_PROCESSES_REDACT") {
+            self.processes.redact_cmdline = matches!(redact.as_str(), "1" | "true" | "yes");
+        }
+
+        if let Some(secs) = env_parse("TCL_INTERVAL")? {
+            self.daemon.interval = Value::env(secs, "TCL_INTERVAL");
+        }
+        if let Some(secs) = env_parse("TCL_JITTER")? {
+            self.daemon.jitter = secs;
+        }
+        if let Some(listen) = env_var("TCL_METRICS_LISTEN") {
+            self.daemon.metrics_listen = Some(Value::env(listen, "TCL_METRICS_LISTEN"));
+        }
+
+        if let Some(dir) = env_var("TCL_SPOOL_DIR") {
+            self.spool.dir = Some(PathBuf::from(dir));
+        }
+        if let Some(max_bytes) = env_parse("TCL_SPOOL_MAX_BYTES")? {
+            self.spool.max_bytes = max_bytes;
+        }
+
+        if let Some(kinds) = env_list("TCL_SINKS") {
+            self.sinks = kinds.into_iter().map(SinkConfig::new).collect();
+        }
+        for sink in &mut self.sinks {
+            sink.apply_env()?;
+        }
+
+        if let Some(level) = env_var("TCL_LOG") {
+            self.log.level = Value::env(level, "TCL_LOG");
+        }
+        if let Some(format) = env_var("TCL_LOG_FORMAT") {
+            self.log.format = Value::env(format, "TCL_LOG_FORMAT");
+        }
+        Ok(())
+    }
+
+    fn apply_overrides(&mut self, overrides: &Overrides) {
+        let flag = |value: &String, flag| Value {
+            value: value.clone(),
+            origin: Origin::Flag(flag),
+        };
+
+        if let Some(url) = &overrides.database_url {
+            self.database.url = Some(url.clone());
+        }
+        if let Some(secs) = overrides.interval {
+            self.daemon.interval = Value {
+                value: secs,
+                origin: Origin::Flag("--interval"),
+            };
+        }
+        if let Some(listen) = &overrides.metrics_listen {
+            self.daemon.metrics_listen = Some(flag(listen, "--metrics-listen"));
+        }
+        if let Some(level) = &overrides.log_level {
+            self.log.level = flag(level, "--log-level");
+        }
+        if let Some(format) = &overrides.log_format {
+            self.log.format = flag(format, "--log-format");
+        }
+    }
+
+    /// Checks every setting the way startup would, returning all errors found
+    /// rather than stopping at the first.
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        let mut check = |result: Result<(), ConfigError>| {
+            if let Err(e) = result {
+                errors.push(e);
+            }
+        };
+
+        // Only the libsql sink needs a database to start.
+        if self.sinks.iter().any(|s| s.kind.value == "libsql") {
+            check(DbTarget::from_config(self).map(drop));
+        }
+        check(logging::filter(&self.log).map(drop));
+        check(logging::LogFormat::from_config(&self.log).map(drop));
+        // These report every problem they find, not just the first.
+        errors.extend(
+            DiskSelection::from_config(&self.disks)
+                .err()
+                .unwrap_or_default(),
+        );
+        errors.extend(
+            InterfaceFilter::from_config(&self.networks)
+                .err()
+                .unwrap_or_default(),
+        );
+        errors.extend(ProcessOptions::from_config(self).err().unwrap_or_default());
+        errors.extend(
+            DaemonOptions::from_config(&self.daemon)
+                .err()
+                .unwrap_or_default(),
+        );
+        for sink in &self.sinks {
+            errors.extend(SinkTarget::from_config(sink).err().unwrap_or_default());
+        }
+        errors
+    }
+}
+
+/// The value of `result`, or `None` after adding its error to `errors`, for
+/// checks that report every problem rather than stopping at the first.
+pub fn gather<T>(errors: &mut Vec<ConfigError>, result: Result<T, ConfigError>) -> Option<T> {
+    result.map_err(|e| errors.push(e)).ok()
+}
+
+/// Parses each value with `parse`, reporting every one that does not parse.
+pub fn parse_each<T>(
+    values: &[Value<String>],
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<T>, Vec<ConfigError>> {
+    let mut errors = Vec::new();
+    let parsed = values
+        .iter()
+        .filter_map(|v| gather(&mut errors, parse(&v.value).map_err(|e| v.error(e))))
+        .collect();
+    if errors.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses a filter's include and exclude lists with [`parse_each`], reporting
+/// the errors in both.
+pub fn parse_filter<T>(
+    filter: &FilterConfig,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<(Vec<T>, Vec<T>), Vec<ConfigError>> {
+    match (
+        parse_each(&filter.include, &parse),
+        parse_each(&filter.exclude, &parse),
+    ) {
+        (Ok(include), Ok(exclude)) => Ok((include, exclude)),
+        (include, exclude) => Err(include
+            .err()
+            .into_iter()
+            .chain(exclude.err())
+            .flatten()
+            .collect()),
+    }
+}
+
+/// A non-empty environment variable.
+fn env_var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|v| !v.trim().is_empty())
+}
+
+fn env_parse<T: FromStr>(key: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    env_var(key)
+        .map(|value| {
+            value.trim().parse().map_err(|e| ConfigError {
+                origin: Origin::Env(key),
+                message: format!("must be a whole number: {}", e),
+            })
+        })
+        .transpose()
+}
+
+/// A comma-separated list of `key=value` pairs.
+fn env_pairs(key: &'static str) -> Result<Option<BTreeMap<String, String>>, ConfigError> {
+    let Some(list) = env_var(key) else {
+        return Ok(None);
+    };
+    let mut pairs = BTreeMap::new();
+    for pair in list.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key_part, value) = pair.split_once('=').ok_or_else(|| ConfigError {
+            origin: Origin::Env(key),
+            message: format!("`{}` must be written as key=value", pair),
+        })?;
+        pairs.insert(key_part.trim().to_string(), value.trim().to_string());
+    }
+    Ok(Some(pairs))
+}
+
+/// A comma-separated environment variable, split into trimmed items.
+fn env_list(key: &'static str) -> Option<Vec<Value<String>>> {
+    env::var(key).ok().map(|list| {
+        list.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| Value::env(item.to_string(), key))
+            .collect()
+    })
+}
+
+/// The config file being applied, for turning byte spans into line numbers.
+struct Source<'a> {
+    path: &'a Path,
+    text: &'a str,
+}
+
+impl Source<'_> {
+    fn origin(&self, span: Range<usize>) -> Origin {
+        let before = &self.text[..span.start.min(self.text.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Origin::File {
+            path: self.path.to_path_buf(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    fn value<T>(&self, spanned: Spanned<T>) -> Value<T> {
+        let origin = self.origin(spanned.span());
+        Value {
+            value: spanned.into_inner(),
+            origin,
+        }
+    }
+
+    fn apply_filter(&self, filter: &mut FilterConfig, file: FileFilter) {
+        if let Some(include) = file.include {
+            filter.include = include.into_iter().map(|v| self.value(v)).collect();
+        }
+        if let Some(exclude) = file.exclude {
+            filter.exclude = exclude.into_iter().map(|v| self.value(v)).collect();
+        }
+    }
+}
+
+/// The config file as written. Every setting is optional so a file only needs
+/// to mention what it changes.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    state_dir: Option<PathBuf>,
+    labels: BTreeMap<String, String>,
+    database: FileDatabase,
+    collectors: FileCollectors,
+    disks: FileFilter,
+    networks: FileFilter,
+    processes: FileProcesses,
+    daemon: FileDaemon,
+    spool: FileSpool,
+    log: FileLog,
+    sinks: Option<Vec<FileSink>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileDatabase {
+    url: Option<String>,
+    auth_token: Option<String>,
+    mode: Option<Spanned<String>>,
+    replica_path: Option<PathBuf>,
+    sync_interval: Option<Spanned<u64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileCollectors {
+    cpu: Option<bool>,
+    memory: Option<bool>,
+    disks: Option<bool>,
+    networks: Option<bool>,
+    sensors: Option<bool>,
+    processes: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileFilter {
+    include: Option<Vec<Spanned<String>>>,
+    exclude: Option<Vec<Spanned<String>>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileProcesses {
+    top: Option<Spanned<usize>>,
+    sort: Option<Vec<Spanned<String>>>,
+    redact_cmdline: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileDaemon {
+    interval: Option<Spanned<u64>>,
+    jitter: Option<u64>,
+    metrics_listen: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileSpool {
+    dir: Option<PathBuf>,
+    max_bytes: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct FileLog {
+    level: Option<Spanned<String>>,
+    format: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileSink {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    format: Option<Spanned<String>>,
+    path: Option<PathBuf>,
+    max_bytes: Option<u64>,
+    keep: Option<usize>,
+    url: Option<Spanned<String>>,
+    #[serde(default)]
+    headers: BTreeMap<String, Spanned<String>>,
+    protocol: Option<Spanned<String>>,
+    address: Option<Spanned<String>>,
+    timeout: Option<Spanned<u64>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Held by tests that set environment variables, which the whole process shares.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn file(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::defaults();
+        config.apply_file(Path::new("tcl.toml"), text)?;
+        Ok(config)
+    }
+
+    fn located(errors: &[ConfigError]) -> Vec<String> {
+        errors
+            .iter()
+            .filter(|e| matches!(e.origin, Origin::File { .. }))
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn layers_defaults_file_dotenv_env_and_flags() {
+        let _env = ENV.lock().unwrap();
+        let dir = env::temp_dir().join(format!("tcl-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tcl.toml");
+        fs::write(
+            &path,
+            "[daemon]\ninterval = 10\nmetrics_listen = \"127.0.0.1:9000\"\n\n\
+             [processes]\ntop = 5\n\n[log]\nlevel = \"error\"\n",
+        )
+        .unwrap();
+        let dotenv_path = dir.join(".env");
+        fs::write(&dotenv_path, "TCL_INTERVAL=30\nTCL_LOG=debug\n").unwrap();
+
+        env::set_var("TCL_LOG", "warn");
+        env::set_var("TCL_METRICS_LISTEN", "127.0.0.1:9001");
+        dotenv::from_path(&dotenv_path).unwrap();
+        let config = Config::load(&Overrides {
+            config: Some(path.clone()),
+            metrics_listen: Some("0.0.0.0:9100".to_string()),
+            ..Overrides::default()
+        });
+        for var in ["TCL_LOG", "TCL_METRICS_LISTEN", "TCL_INTERVAL"] {
+            env::remove_var(var);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        let config = config.unwrap();
+
+        // Default, untouched by any layer.
+        assert_eq!(config.log.format.value, "text");
+        assert_eq!(config.log.format.origin.to_string(), "default");
+        // The file over the default.
+        assert_eq!(config.processes.top.value, 5);
+        assert_eq!(
+            config.processes.top.origin.to_string(),
+            format!("{}:6:7", path.display())
+        );
+        // `.env` over the file.
+        assert_eq!(config.daemon.interval.value, 30);
+        assert_eq!(config.daemon.interval.origin.to_string(), "TCL_INTERVAL");
+        // The environment over `.env`.
+        assert_eq!(config.log.level.value, "warn");
+        assert_eq!(config.log.level.origin.to_string(), "TCL_LOG");
+        // Flags over everything.
+        let listen = config.daemon.metrics_listen.unwrap();
+        assert_eq!(listen.value, "0.0.0.0:9100");
+        assert_eq!(listen.origin.to_string(), "--metrics-listen");
+    }
+
+    #[test]
+    fn reports_where_an_invalid_setting_was_written() {
+        let config = file(
+            "[daemon]\ninterval = 0\n\n[log]\nformat = \"xml\"\n\n\
+             [processes]\nsort = [\"cpu\", \"colour\"]\n\n\
+             [collectors]\nprocesses = true\n",
+        )
+        .unwrap();
+        let errors = located(&config.validate());
+        assert_eq!(
+            errors,
+            [
+                "tcl.toml:5:10: unknown log format `xml` (expected text or json)",
+                "tcl.toml:8:16: unknown process sort key `colour` (expected cpu or memory)",
+                "tcl.toml:2:12: interval must be greater than zero",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_where_the_file_does_not_parse() {
+        let error = file("labels = { env = \"prod\" }\n\n[daemon]\ninterval = \"soon\"\n")
+            .err()
+            .unwrap();
+        assert_eq!(error.origin.to_string(), "tcl.toml:4:12");
+        assert!(
+            error.to_string().starts_with("tcl.toml:4:12: "),
+            "{}",
+            error
+        );
+
+        let error = file("[daemon]\n  intervall = 5\n").err().unwrap();
+        assert_eq!(error.origin.to_string(), "tcl.toml:2:3");
+    }
+}
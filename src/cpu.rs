@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use sysinfo::System;
+
+/// Time between the two CPU samples that usage is computed from. sysinfo needs
+/// at least `MINIMUM_CPU_UPDATE_INTERVAL` for a meaningful reading; a full second
+/// smooths out short bursts.
+pub const SAMPLE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize)]
+pub struct CpuCore {
+    pub name: String,
+    pub usage_percent: f32,
+    pub frequency_mhz: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CpuInfo {
+    pub brand: String,
+    pub physical_cores: Option<usize>,
+    pub logical_cores: usize,
+    pub usage_percent: f32,
+    pub frequency_mhz: u64,
+    pub load_one: f64,
+    pub load_five: f64,
+    pub load_fifteen: f64,
+    pub cores: Vec<CpuCore>,
+}
+
+/// Reads CPU usage from a `System` whose CPUs were refreshed twice,
+/// [`SAMPLE_WINDOW`] apart.
+pub fn get_cpu_info(sys: &System) -> CpuInfo {
+    let cores: Vec<CpuCore> = sys
+        .cpus()
+        .iter()
+        .map(|cpu| CpuCore {
+            name: cpu.name().to_string(),
+            usage_percent: cpu.cpu_usage(),
+            frequency_mhz: cpu.frequency(),
+        })
+        .collect();
+
+    let frequency_mhz = match cores.len() as u64 {
+        0 => 0,
+        n => cores.iter().map(|c| c.frequency_mhz).sum::<u64>() / n,
+    };
+    let load = System::load_average();
+
+    CpuInfo {
+        brand: sys
+            .cpus()
+            .first()
+            .map(|cpu| cpu.brand().to_string())
+            .unwrap_or_default(),
+        physical_cores: sys.physical_core_count(),
+        logical_cores: cores.len(),
+        usage_percent: sys.global_cpu_info().cpu_usage(),
+        frequency_mhz,
+        load_one: load.one,
+        load_five: load.five,
+        load_fifteen: load.fifteen,
+        cores,
+    }
+}
@@ -0,0 +1,111 @@
+use crate::config::{gather, ConfigError, DaemonConfig};
+use crate::prometheus::Exporter;
+use crate::sink::Sinks;
+use crate::Collector;
+use rand::Rng;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Instant, MissedTickBehavior};
+use tracing::info;
+
+pub struct DaemonOptions {
+    pub interval: Duration,
+    pub jitter: Duration,
+    pub metrics_listen: Option<SocketAddr>,
+}
+
+impl DaemonOptions {
+    /// Builds the options from `[daemon]`, where both values are in seconds.
+    /// Every invalid setting is reported.
+    pub fn from_config(daemon: &DaemonConfig) -> Result<Self, Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        if daemon.interval.value == 0 {
+            errors.push(daemon.interval.error("interval must be greater than zero"));
+        }
+
+        let metrics_listen = daemon.metrics_listen.as_ref().and_then(|listen| {
+            let address = listen.value.parse().map_err(|_| {
+                listen.error(format!(
+                    "invalid metrics address `{}` (expected e.g. 127.0.0.1:9464)",
+                    listen.value
+                ))
+            });
+            gather(&mut errors, address)
+        });
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(DaemonOptions {
+            interval: Duration::from_secs(daemon.interval.value),
+            jitter: Duration::from_secs(daemon.jitter),
+            metrics_listen,
+        })
+    }
+
+    fn random_jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        let millis = rand::thread_rng().gen_range(0..=self.jitter.as_millis() as u64);
+        Duration::from_millis(millis)
+    }
+}
+
+/// Collects a snapshot and writes it to every sink each interval until SIGINT
+/// or SIGTERM.
+///
+/// Each cycle is delayed by a random amount up to the configured jitter so a
+/// fleet started at the same moment spreads its writes out. A signal received
+/// mid-cycle lets the running writes finish before shutting down. Sinks that
+/// need it, such as embedded replicas, are also synced on their own interval
+/// and once more on the way out.
+/// With `metrics_listen` set, each snapshot is also served to Prometheus until
+/// the next one replaces it.
+pub async fn run(
+    sinks: &mut Sinks,
+    collector: &mut Collector,
+    options: &DaemonOptions,
+) -> crate::error::Result<()> {
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let exporter = options.metrics_listen.map(Exporter::serve).transpose()?;
+
+    let mut scheduled = Instant::now();
+    let next_collection = time::sleep_until(scheduled + options.random_jitter());
+    tokio::pin!(next_collection);
+
+    let sync_interval = sinks.sync_interval();
+    let mut sync_ticker = time::interval(sync_interval.unwrap_or(options.interval));
+    sync_ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    sync_ticker.reset();
+
+    loop {
+        tokio::select! {
+            _ = &mut next_collection => {
+                let snapshot = collector.collect().await;
+                if let Some(exporter) = &exporter {
+                    exporter.publish(&snapshot);
+                }
+                // Failures are logged per sink, and libsql spools its own.
+                let _ = sinks.write(&snapshot).await;
+
+                scheduled = (scheduled + options.interval).max(Instant::now());
+                next_collection
+                    .as_mut()
+                    .reset(scheduled + options.random_jitter());
+            }
+            _ = sync_ticker.tick(), if sync_interval.is_some() => {
+                // Each sink logs its own sync failures.
+                let _ = sinks.sync().await;
+            }
+            _ = sigint.recv() => break,
+            _ = sigterm.recv() => break,
+        }
+    }
+
+    info!("shutting down");
+    let _ = sinks.sync().await;
+    Ok(())
+}
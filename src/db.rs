@@ -0,0 +1,616 @@
+use crate::config::{Config, ConfigError, Origin};
+use crate::error::{Error, Result, SchemaError};
+use crate::host::SystemInfo;
+use crate::migrations;
+use crate::sink::Sink;
+use crate::spool::Spool;
+use crate::Snapshot;
+use async_trait::async_trait;
+use libsql::{params, Builder, Connection, Database};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
+
+const LAST_SYNC_FILE: &str = "last-sync";
+
+/// Where the database lives.
+pub enum DbTarget {
+    /// A libsql/SQLite file opened in-process.
+    Local(PathBuf),
+    /// A libsql server reached over HTTP.
+    Remote { url: String, token: String },
+    /// A local embedded replica of a libsql server. Reads are served from the
+    /// file at `path`, which is pulled from `url` every `sync_interval`.
+    ///
+    /// libsql forwards every write on a replica to the primary, so snapshots
+    /// are not written through it. They go to the spool instead, which each
+    /// sync drains into `url`; see [`Db::record`].
+    Replica {
+        path: PathBuf,
+        url: String,
+        token: String,
+        sync_interval: Duration,
+    },
+}
+
+impl DbTarget {
+    /// Picks the target from `[database]`, which the environment sets from
+    /// `LIBSQL_URL`, `LIBSQL_AUTH_TOKEN`, `LIBSQL_MODE`, `LIBSQL_REPLICA_PATH` and
+    /// `LIBSQL_SYNC_INTERVAL`.
+    ///
+    /// The mode may be `local`, `remote` or `replica`; when unset it follows the
+    /// URL scheme: `file:` or a bare path is local, while `libsql://` and
+    /// `http(s)://` are remote. A URL is required unless the mode is `local`,
+    /// in which case it defaults to `tcl.db` in the state directory, so a
+    /// missing or misspelled URL is reported rather than silently writing to a
+    /// local file. Replicas are kept at the replica path (default
+    /// `replica.db` in the state directory) and synced every sync interval
+    /// (default 60 seconds).
+    pub fn from_config(config: &Config) -> std::result::Result<Self, ConfigError> {
+        let database = &config.database;
+        let url = database.url.clone().filter(|u| !u.is_empty());
+        let token = database.auth_token.clone();
+        let mode = database.mode.as_ref();
+        if let Some(mode) =
+            mode.filter(|m| !matches!(m.value.as_str(), "local" | "remote" | "replica"))
+        {
+            return Err(mode.error(format!(
+                "unknown database mode `{}` (expected local, remote or replica)",
+                mode.value
+            )));
+        }
+
+        let url = match (url, mode) {
+            (Some(url), _) => url,
+            (None, Some(mode)) if mode.value != "local" => {
+                return Err(mode.error(format!(
+                    "database mode is {} but no database URL is set",
+                    mode.value
+                )))
+            }
+            (None, Some(_)) => return Ok(DbTarget::Local(config.state_dir.join("tcl.db"))),
+            (None, None) => {
+                return Err(ConfigError {
+                    origin: Origin::Default,
+                    message: "no database URL is set; set LIBSQL_URL or [database] url, or \
+                              LIBSQL_MODE=local to use tcl.db in the state directory"
+                        .to_string(),
+                })
+            }
+        };
+
+        let remote_scheme = ["libsql://", "http://", "https://"]
+            .iter()
+            .any(|scheme| url.starts_with(scheme));
+
+        match mode.map(|m| m.value.as_str()) {
+            Some("remote") => Ok(DbTarget::Remote { url, token }),
+            Some("local") => Ok(DbTarget::Local(local_path(&url))),
+            Some("replica") => {
+                if database.sync_interval.value == 0 {
+                    return Err(database
+                        .sync_interval
+                        .error("replica sync interval must be greater than zero"));
+                }
+
+                Ok(DbTarget::Replica {
+                    path: database
+                        .replica_path
+                        .clone()
+                        .unwrap_or_else(|| config.state_dir.join("replica.db")),
+                    url,
+                    token,
+                    sync_interval: Duration::from_secs(database.sync_interval.value),
+                })
+            }
+            _ if remote_scheme => Ok(DbTarget::Remote { url, token }),
+            _ => Ok(DbTarget::Local(local_path(&url))),
+        }
+    }
+}
+
+/// Strips a `file:` or `file://` prefix from a local database URL.
+fn local_path(url: &str) -> PathBuf {
+    let path = url
+        .strip_prefix("file://")
+        .or_else(|| url.strip_prefix("file:"))
+        .unwrap_or(url);
+    PathBuf::from(path)
+}
+
+fn create_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        Some(dir) => std::fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+}
+
+/// An open database, its connection and the spool for snapshots it could not
+/// take or, as a replica, has not pushed yet.
+pub struct Db {
+    database: Database,
+    conn: Connection,
+    spool: Spool,
+    state_dir: PathBuf,
+    sync_interval: Option<Duration>,
+    last_sync: Option<SystemTime>,
+    migrated: bool,
+}
+
+impl Db {
+    /// Brings the schema up to date the first time the database is reachable.
+    /// A replica is synced first so migrations see the primary's schema version.
+    pub async fn ensure_schema(&mut self) -> Result<()> {
+        if !self.migrated {
+            self.pull().await?;
+            let version = migrations::migrate(&self.conn)
+                .await
+                .map_err(Error::schema)?;
+            debug!(version, "schema up to date");
+            self.migrated = true;
+        }
+        Ok(())
+    }
+
+    /// The schema version the database is at, without migrating it.
+    pub async fn schema_version(&self) -> Result<i64> {
+        migrations::applied_version(&self.conn)
+            .await
+            .map_err(Error::schema)
+    }
+
+    /// Fails unless the schema has every migration this build knows, for
+    /// commands that only read and so must not migrate.
+    pub async fn check_schema(&self) -> Result<()> {
+        let version = self.schema_version().await?;
+        let latest = migrations::latest_version();
+        if version < latest {
+            return Err(Error::Schema(SchemaError::Outdated { version, latest }));
+        }
+        Ok(())
+    }
+
+    pub fn conn(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Spools a snapshot, then writes everything spooled to the database
+    /// oldest-first. The spool acts as a write-ahead log: a write that fails,
+    /// or is abandoned after the sink's timeout, leaves the snapshot there to be
+    /// retried on the next call. If the spool cannot be written, the snapshot
+    /// is written straight to the database after the spool is replayed.
+    ///
+    /// An embedded replica only spools the snapshot, without touching the
+    /// network; [`Db::sync`] pushes it upstream.
+    pub async fn record(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let spooled = match self.spool.push(snapshot) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    run = snapshot.id.as_str(),
+                    error = %e,
+                    "could not spool snapshot, writing it directly"
+                );
+                false
+            }
+        };
+        if spooled && self.sync_interval.is_some() {
+            debug!(
+                run = snapshot.id.as_str(),
+                "spooled snapshot for the next sync"
+            );
+            return Ok(());
+        }
+
+        let mut result = self.ensure_schema().await;
+        if result.is_ok() {
+            result = self
+                .spool
+                .replay(&self.conn)
+                .await
+                .map(|_| ())
+                .map_err(Error::write);
+        }
+        if result.is_ok() && !spooled {
+            let started = Instant::now();
+            result = insert_into_db(&self.conn, snapshot)
+                .await
+                .map_err(Error::write);
+            if result.is_ok() {
+                info!(
+                    run = snapshot.id.as_str(),
+                    elapsed_ms = started.elapsed().as_secs_f64() * 1000.0,
+                    "recorded snapshot"
+                );
+            }
+        }
+        if let Err(e) = &result {
+            if spooled {
+                warn!(run = snapshot.id.as_str(), error = %e, "spooled snapshot for retry");
+            } else {
+                error!(
+                    run = snapshot.id.as_str(),
+                    error = %e,
+                    "could not record or spool snapshot, it is lost"
+                );
+            }
+        }
+        result
+    }
+
+    /// How often an embedded replica should be synced; `None` for other modes.
+    pub fn sync_interval(&self) -> Option<Duration> {
+        self.sync_interval
+    }
+
+    /// When the embedded replica last synced successfully, including syncs
+    /// made by earlier runs and recorded in the `last-sync` state file.
+    pub fn last_sync(&self) -> Option<SystemTime> {
+        self.last_sync.or_else(|| {
+            let millis: u64 = std::fs::read_to_string(self.state_dir.join(LAST_SYNC_FILE))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()?;
+            Some(UNIX_EPOCH + Duration::from_millis(millis))
+        })
+    }
+
+    /// Pushes the spooled snapshots of an embedded replica to the primary, then
+    /// pulls the primary's changes into the replica. Does nothing in other modes.
+    pub async fn sync(&mut self) -> Result<()> {
+        if self.sync_interval.is_none() {
+            return Ok(());
+        }
+
+        self.ensure_schema().await?;
+        let pushed = self.spool.replay(&self.conn).await.map_err(Error::write)?;
+        if pushed > 0 {
+            info!(snapshots = pushed, "pushed spooled snapshots upstream");
+        }
+        self.pull().await
+    }
+
+    /// Pulls changes from the primary into an embedded replica, recording the
+    /// time of success in the `last-sync` state file. Does nothing in other modes.
+    async fn pull(&mut self) -> Result<()> {
+        if self.sync_interval.is_none() {
+            return Ok(());
+        }
+
+        self.database.sync().await.map_err(Error::Connection)?;
+
+        let now = SystemTime::now();
+        self.last_sync = Some(now);
+        let millis = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+        let path = self.state_dir.join(LAST_SYNC_FILE);
+        create_parent_dir(&path)?;
+        std::fs::write(&path, format!("{}\n", millis))?;
+        debug!("synced replica");
+        Ok(())
+    }
+}
+
+/// The `libsql` sink.
+#[async_trait]
+impl Sink for Db {
+    fn name(&self) -> &str {
+        "libsql"
+    }
+
+    async fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.record(snapshot).await
+    }
+
+    fn sync_interval(&self) -> Option<Duration> {
+        Db::sync_interval(self)
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        let result = Db::sync(self).await;
+        if let Err(e) = &result {
+            match self.last_sync() {
+                Some(at) => warn!(
+                    error = %e,
+                    last_sync_secs_ago = at.elapsed().map_or(0, |d| d.as_secs()),
+                    "replica sync failed"
+                ),
+                None => warn!(error = %e, "replica sync failed, never synced"),
+            }
+        }
+        result
+    }
+}
+
+/// Opens the database without touching the network, so an unreachable server
+/// does not stop snapshots from being collected and spooled. The schema is
+/// migrated on first use; see [`Db::ensure_schema`].
+pub async fn init_db(config: &Config) -> Result<Db> {
+    let target = DbTarget::from_config(config)?;
+    let spool = Spool::new(config, &target);
+
+    let (database, sync_interval) = match target {
+        DbTarget::Local(path) => {
+            create_parent_dir(&path)?;
+            let database = Builder::new_local(path)
+                .build()
+                .await
+                .map_err(Error::Connection)?;
+            (database, None)
+        }
+        DbTarget::Remote { url, token } => {
+            let database = Builder::new_remote(url, token)
+                .build()
+                .await
+                .map_err(Error::Connection)?;
+            (database, None)
+        }
+        DbTarget::Replica {
+            path,
+            url,
+            token,
+            sync_interval,
+        } => {
+            create_parent_dir(&path)?;
+            let database = Builder::new_remote_replica(path, url, token)
+                .build()
+                .await
+                .map_err(Error::Connection)?;
+            (database, Some(sync_interval))
+        }
+    };
+
+    let conn = database.connect().map_err(Error::Connection)?;
+    Ok(Db {
+        database,
+        conn,
+        spool,
+        state_dir: config.state_dir.clone(),
+        sync_interval,
+        last_sync: None,
+        migrated: false,
+    })
+}
+
+/// Records one collection run and everything collected with it in a single
+/// transaction. A snapshot whose run is already stored, e.g. a spooled one
+/// replayed twice, changes nothing, not even the host's last-seen values.
+///
+/// Sizes are stored as raw bytes; conversions belong in queries such as the
+/// `disk_usage` view.
+pub async fn insert_into_db(conn: &Connection, snapshot: &Snapshot) -> libsql::Result<()> {
+    let tx = conn.transaction().await?;
+
+    let mut rows = tx
+        .query(
+            "SELECT 1 FROM runs WHERE idempotency_key = ?1",
+            params![snapshot.id.as_str()],
+        )
+        .await?;
+    let recorded = rows.next().await?.is_some();
+    drop(rows);
+    if recorded {
+        return tx.rollback().await;
+    }
+
+    let host_id = upsert_host(&tx, snapshot.collected_at, &snapshot.system).await?;
+
+    let inserted = tx
+        .execute(
+            "INSERT INTO runs (idempotency_key, collected_at, host_id, machine_id) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (idempotency_key) DO NOTHING",
+            params![
+                snapshot.id.as_str(),
+                snapshot.collected_at,
+                host_id,
+                snapshot.system.machine_id.as_str(),
+            ],
+        )
+        .await?;
+    if inserted == 0 {
+        // Recorded by another writer since the check above.
+        return tx.rollback().await;
+    }
+    let run_id = tx.last_insert_rowid();
+
+    for disk in &snapshot.disks {
+        tx.execute(
+            "INSERT INTO disks (run_id, name, mount_point, file_system, kind, total_bytes, available_bytes, used_bytes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                run_id,
+                disk.name.as_str(),
+                disk.mount_point.as_str(),
+                disk.file_system.as_str(),
+                disk.kind.as_str(),
+                disk.total_bytes,
+                disk.available_bytes,
+                disk.used_bytes,
+            ],
+        )
+        .await?;
+    }
+
+    if let Some(cpu) = &snapshot.cpu {
+        tx.execute(
+            "INSERT INTO cpus (run_id, brand, physical_cores, logical_cores, usage_percent, frequency_mhz, load_one, load_five, load_fifteen) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                run_id,
+                cpu.brand.as_str(),
+                cpu.physical_cores.map(|n| n as u32),
+                cpu.logical_cores as u32,
+                cpu.usage_percent,
+                cpu.frequency_mhz,
+                cpu.load_one,
+                cpu.load_five,
+                cpu.load_fifteen,
+            ],
+        )
+        .await?;
+
+        for core in &cpu.cores {
+            tx.execute(
+                "INSERT INTO cpu_cores (run_id, name, usage_percent, frequency_mhz) VALUES (?1, ?2, ?3, ?4)",
+                params![run_id, core.name.as_str(), core.usage_percent, core.frequency_mhz],
+            )
+            .await?;
+        }
+    }
+
+    if let Some(memory) = &snapshot.memory {
+        tx.execute(
+            "INSERT INTO memory (run_id, total_bytes, used_bytes, free_bytes, available_bytes, swap_total_bytes, swap_used_bytes, swap_free_bytes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                run_id,
+                memory.total_bytes,
+                memory.used_bytes,
+                memory.free_bytes,
+                memory.available_bytes,
+                memory.swap_total_bytes,
+                memory.swap_used_bytes,
+                memory.swap_free_bytes,
+            ],
+        )
+        .await?;
+    }
+
+    for network in &snapshot.networks {
+        tx.execute(
+            "INSERT INTO network_interfaces (run_id, name, received_bytes, transmitted_bytes, packets_received, packets_transmitted, errors_received, errors_transmitted, receive_rate, transmit_rate, packets_received_rate, packets_transmitted_rate) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                run_id,
+                network.name.as_str(),
+                network.received_bytes,
+                network.transmitted_bytes,
+                network.packets_received,
+                network.packets_transmitted,
+                network.errors_received,
+                network.errors_transmitted,
+                network.receive_rate,
+                network.transmit_rate,
+                network.packets_received_rate,
+                network.packets_transmitted_rate,
+            ],
+        )
+        .await?;
+    }
+
+    for process in &snapshot.processes {
+        tx.execute(
+            "INSERT INTO processes (run_id, sort_key, rank, pid, name, cmd, user, memory_bytes, cpu_percent, disk_read_bytes, disk_written_bytes, start_time) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                run_id,
+                process.sort_key.as_str(),
+                process.rank as u32,
+                process.pid,
+                process.name.as_str(),
+                process.cmd.as_str(),
+                process.user.as_deref(),
+                process.memory_bytes,
+                process.cpu_percent,
+                process.disk_read_bytes,
+                process.disk_written_bytes,
+                process.start_time,
+            ],
+        )
+        .await?;
+    }
+
+    for sensor in &snapshot.sensors {
+        tx.execute(
+            "INSERT INTO sensors (run_id, label, temperature, max, critical) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                run_id,
+                sensor.label.as_str(),
+                sensor.temperature,
+                sensor.max,
+                sensor.critical,
+            ],
+        )
+        .await?;
+    }
+
+    tx.commit().await
+}
+
+/// Inserts or refreshes the `hosts` row for this machine, keyed by its machine
+/// id, records renames in `host_names`, and returns the host's row id.
+async fn upsert_host(conn: &Connection, seen_at: i64, system: &SystemInfo) -> libsql::Result<i64> {
+    // Labels are stored as a JSON object, or NULL when there are none.
+    let labels = if system.labels.is_empty() {
+        None
+    } else {
+        let json = serde_json::to_string(&system.labels)
+            .map_err(|e| libsql::Error::ToSqlConversionFailure(Box::new(e)))?;
+        Some(json)
+    };
+
+    // Rows written before machine ids existed are claimed by the first agent
+    // reporting the same host name, so their history carries over.
+    conn.execute(
+        "UPDATE hosts SET machine_id = ?1
+        WHERE id = (SELECT id FROM hosts WHERE machine_id IS NULL AND host_name = ?2 ORDER BY last_seen_at DESC LIMIT 1)
+            AND NOT EXISTS (SELECT 1 FROM hosts WHERE machine_id = ?1)",
+        params![system.machine_id.as_str(), system.system_host_name.as_str()],
+    )
+    .await?;
+
+    let mut rows = conn
+        .query(
+            "INSERT INTO hosts (machine_id, host_name, system_name, kernel_version, os_version, long_os_version, distribution_id, cpu_arch, boot_time, uptime, total_memory_bytes, labels, first_seen_at, last_seen_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13)
+            ON CONFLICT (machine_id) DO UPDATE SET
+                host_name = excluded.host_name,
+                system_name = excluded.system_name,
+                kernel_version = excluded.kernel_version,
+                os_version = excluded.os_version,
+                long_os_version = excluded.long_os_version,
+                distribution_id = excluded.distribution_id,
+                cpu_arch = excluded.cpu_arch,
+                boot_time = excluded.boot_time,
+                uptime = excluded.uptime,
+                total_memory_bytes = excluded.total_memory_bytes,
+                labels = excluded.labels,
+                last_seen_at = excluded.last_seen_at
+            RETURNING id",
+            params![
+                system.machine_id.as_str(),
+                system.system_host_name.as_str(),
+                system.system_name.as_str(),
+                system.kernel_version.as_str(),
+                system.os_version.as_str(),
+                system.long_os_version.as_str(),
+                system.distribution_id.as_str(),
+                system.cpu_arch.as_str(),
+                system.boot_time,
+                system.uptime,
+                system.total_memory_bytes,
+                labels,
+                seen_at,
+            ],
+        )
+        .await?;
+
+    let host_id: i64 = match rows.next().await? {
+        Some(row) => row.get(0)?,
+        None => return Err(libsql::Error::QueryReturnedNoRows),
+    };
+    drop(rows);
+
+    let extended = conn
+        .execute(
+            "UPDATE host_names SET last_seen_at = ?3
+            WHERE id = (SELECT id FROM host_names WHERE host_id = ?1 ORDER BY id DESC LIMIT 1)
+                AND host_name = ?2",
+            params![host_id, system.system_host_name.as_str(), seen_at],
+        )
+        .await?;
+    if extended == 0 {
+        conn.execute(
+            "INSERT INTO host_names (host_id, host_name, first_seen_at, last_seen_at) VALUES (?1, ?2, ?3, ?3)",
+            params![host_id, system.system_host_name.as_str(), seen_at],
+        )
+        .await?;
+    }
+
+    Ok(host_id)
+}
@@ -0,0 +1,180 @@
+use crate::config::{parse_filter, ConfigError, FilterConfig};
+use glob::Pattern;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sysinfo::{Disk, DiskKind, Disks};
+
+#[derive(Serialize, Deserialize)]
+pub struct DiskInfo {
+    pub name: String,
+    pub mount_point: String,
+    pub file_system: String,
+    pub kind: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    pub used_bytes: u64,
+}
+
+impl DiskInfo {
+    fn from_disk(disk: &Disk) -> Self {
+        let kind = if disk.is_removable() {
+            "removable"
+        } else {
+            match disk.kind() {
+                DiskKind::SSD => "SSD",
+                DiskKind::HDD => "HDD",
+                DiskKind::Unknown(_) => "unknown",
+            }
+        };
+
+        DiskInfo {
+            name: disk.name().to_string_lossy().into_owned(),
+            mount_point: disk.mount_point().to_string_lossy().into_owned(),
+            file_system: disk.file_system().to_string_lossy().into_owned(),
+            kind: kind.to_string(),
+            total_bytes: disk.total_space(),
+            available_bytes: disk.available_space(),
+            used_bytes: disk.total_space().saturating_sub(disk.available_space()),
+        }
+    }
+}
+
+/// A single rule used to decide whether a disk is recorded.
+///
+/// Rules are written as `kind:pattern`, e.g. `name:nvme*`, `name-regex:^sd[a-z]$`,
+/// `mount:/home*`, `fs:ext4`, or just `all`.
+pub enum DiskRule {
+    All,
+    Name(Pattern),
+    NameRegex(Regex),
+    MountPoint(Pattern),
+    FileSystem(String),
+}
+
+impl DiskRule {
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let rule = rule.trim();
+        if rule == "all" {
+            return Ok(DiskRule::All);
+        }
+
+        let (kind, pattern) = rule
+            .split_once(':')
+            .ok_or_else(|| format!("disk rule `{}` must be `all` or `kind:pattern`", rule))?;
+
+        match kind {
+            "name" => Pattern::new(pattern)
+                .map(DiskRule::Name)
+                .map_err(|e| format!("invalid glob in disk rule `{}`: {}", rule, e)),
+            "name-regex" => Regex::new(pattern)
+                .map(DiskRule::NameRegex)
+                .map_err(|e| format!("invalid regex in disk rule `{}`: {}", rule, e)),
+            "mount" => Pattern::new(pattern)
+                .map(DiskRule::MountPoint)
+                .map_err(|e| format!("invalid glob in disk rule `{}`: {}", rule, e)),
+            "fs" => Ok(DiskRule::FileSystem(pattern.to_string())),
+            _ => Err(format!(
+                "unknown disk rule kind `{}` (expected name, name-regex, mount or fs)",
+                kind
+            )),
+        }
+    }
+
+    pub fn matches(&self, disk: &Disk) -> bool {
+        match self {
+            DiskRule::All => true,
+            DiskRule::Name(pattern) => pattern.matches(&disk.name().to_string_lossy()),
+            DiskRule::NameRegex(regex) => regex.is_match(&disk.name().to_string_lossy()),
+            DiskRule::MountPoint(pattern) => pattern.matches_path(disk.mount_point()),
+            DiskRule::FileSystem(fs) => disk.file_system().to_string_lossy() == fs.as_str(),
+        }
+    }
+}
+
+/// Include and exclude lists of [`DiskRule`]s.
+///
+/// A disk is selected when it matches any include rule (or the include list is
+/// empty) and matches none of the exclude rules.
+pub struct DiskSelection {
+    include: Vec<DiskRule>,
+    exclude: Vec<DiskRule>,
+}
+
+impl DiskSelection {
+    /// Builds the selection from the `[disks]` rules, which the environment
+    /// sets from comma-separated `TCL_DISK_INCLUDE` and `TCL_DISK_EXCLUDE`.
+    pub fn from_config(filter: &FilterConfig) -> Result<Self, Vec<ConfigError>> {
+        let (include, exclude) = parse_filter(filter, DiskRule::parse)?;
+        Ok(DiskSelection { include, exclude })
+    }
+
+    pub fn matches(&self, disk: &Disk) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|r| r.matches(disk));
+        included && !self.exclude.iter().any(|r| r.matches(disk))
+    }
+}
+
+pub fn get_disk_info(disks: &Disks, selection: &DiskSelection) -> Vec<DiskInfo> {
+    disks
+        .iter()
+        .filter(|disk| selection.matches(disk))
+        .map(DiskInfo::from_disk)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn error(rule: &str) -> String {
+        match DiskRule::parse(rule) {
+            Ok(_) => panic!("`{}` should not parse", rule),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn parses_each_kind_of_rule() {
+        assert!(matches!(DiskRule::parse("all"), Ok(DiskRule::All)));
+        assert!(matches!(DiskRule::parse("  all "), Ok(DiskRule::All)));
+        assert!(matches!(
+            DiskRule::parse("name:nvme*"),
+            Ok(DiskRule::Name(p)) if p.matches("nvme0n1") && !p.matches("sda")
+        ));
+        assert!(matches!(
+            DiskRule::parse("name-regex:^sd[a-z]$"),
+            Ok(DiskRule::NameRegex(r)) if r.is_match("sdb") && !r.is_match("sdb1")
+        ));
+        assert!(matches!(
+            DiskRule::parse("mount:/home*"),
+            Ok(DiskRule::MountPoint(p)) if p.matches_path(Path::new("/home/user"))
+        ));
+        assert!(matches!(
+            DiskRule::parse("fs:ext4"),
+            Ok(DiskRule::FileSystem(fs)) if fs == "ext4"
+        ));
+        // Only the first colon separates the kind from the pattern.
+        assert!(matches!(
+            DiskRule::parse("mount:/mnt/c:"),
+            Ok(DiskRule::MountPoint(p)) if p.as_str() == "/mnt/c:"
+        ));
+    }
+
+    #[test]
+    fn explains_rules_that_do_not_parse() {
+        assert_eq!(
+            error("nvme0n1"),
+            "disk rule `nvme0n1` must be `all` or `kind:pattern`"
+        );
+        assert_eq!(
+            error("label:data"),
+            "unknown disk rule kind `label` (expected name, name-regex, mount or fs)"
+        );
+        assert!(error("name:sd[a").starts_with("invalid glob in disk rule `name:sd[a`: "));
+        assert!(error("mount:/mnt/***").starts_with("invalid glob in disk rule `mount:/mnt/***`: "));
+        assert!(
+            error("name-regex:sd(a").starts_with("invalid regex in disk rule `name-regex:sd(a`: ")
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::config::Config;
+use crate::db::{init_db, DbTarget};
+use crate::error::Error;
+use crate::host::machine_id;
+use crate::migrations::latest_version;
+use crate::output::Table;
+use crate::spool::Spool;
+use serde_json::Value;
+use std::fs;
+
+#[derive(Clone, Copy)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+/// Results of `tcl doctor`, one row per check.
+pub struct Report {
+    pub table: Table,
+    /// The first failed check, whose exit code the command exits with.
+    pub failure: Option<Error>,
+}
+
+impl Report {
+    fn check(&mut self, name: &str, status: Status, detail: impl Into<String>) {
+        self.table.push(vec![
+            Value::from(name),
+            Value::from(status.as_str()),
+            Value::from(detail.into()),
+        ]);
+    }
+
+    fn fail(&mut self, name: &str, error: Error) {
+        self.check(name, Status::Fail, error.to_string());
+        self.failure.get_or_insert(error);
+    }
+}
+
+/// Checks what the agent needs to run: valid settings, a writable state
+/// directory, a host id, and a reachable database at the current schema
+/// version. Nothing is migrated or written to the database.
+pub async fn run(config: &Config) -> Report {
+    let mut report = Report {
+        table: Table::new(["check", "status", "detail"]),
+        failure: None,
+    };
+
+    let errors = config.validate();
+    match errors.first() {
+        None => report.check(
+            "config",
+            Status::Ok,
+            config
+                .path
+                .as_ref()
+                .map_or("no config file".to_string(), |p| p.display().to_string()),
+        ),
+        Some(_) => {
+            let detail: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            report.fail("config", Error::Config(detail.join("; ")));
+        }
+    }
+
+    let probe = config.state_dir.join(".doctor");
+    match fs::create_dir_all(&config.state_dir)
+        .and_then(|()| fs::write(&probe, b""))
+        .and_then(|()| fs::remove_file(&probe))
+    {
+        Ok(()) => report.check(
+            "state dir",
+            Status::Ok,
+            config.state_dir.display().to_string(),
+        ),
+        Err(e) => report.fail("state dir", Error::Io(e)),
+    }
+
+    match machine_id(&config.state_dir) {
+        Ok(id) => report.check("host id", Status::Ok, id),
+        Err(e) => report.fail(
+            "host id",
+            Error::Collection(format!("could not determine host id: {}", e)),
+        ),
+    }
+
+    // An invalid database setting is reported with the config above.
+    if let Ok(target) = DbTarget::from_config(config) {
+        match Spool::new(config, &target).backlog() {
+            Ok((0, _)) => report.check("spool", Status::Ok, "empty"),
+            Ok((count, bytes)) => report.check(
+                "spool",
+                Status::Warn,
+                format!("{} snapshot(s), {} bytes waiting for replay", count, bytes),
+            ),
+            Err(e) => report.fail("spool", Error::Io(e)),
+        }
+    }
+
+    let db = match init_db(config).await {
+        Ok(db) => db,
+        Err(e) => {
+            report.fail("database", e);
+            return report;
+        }
+    };
+    match db.schema_version().await {
+        Ok(version) => {
+            report.check("database", Status::Ok, "reachable");
+            let latest = latest_version();
+            if version < latest {
+                report.check(
+                    "schema",
+                    Status::Warn,
+                    format!(
+                        "version {}, {} migration(s) pending; run `tcl migrate`",
+                        version,
+                        latest - version
+                    ),
+                );
+            } else if version > latest {
+                report.check(
+                    "schema",
+                    Status::Warn,
+                    format!("version {} is newer than this build ({})", version, latest),
+                );
+            } else {
+                report.check("schema", Status::Ok, format!("version {}", version));
+            }
+        }
+        Err(e) => report.fail("database", e),
+    }
+
+    if db.sync_interval().is_some() {
+        match db.last_sync() {
+            Some(at) => report.check(
+                "replica sync",
+                Status::Ok,
+                format!(
+                    "last synced {}s ago",
+                    at.elapsed().map_or(0, |d| d.as_secs())
+                ),
+            ),
+            None => report.check("replica sync", Status::Warn, "never synced"),
+        }
+    }
+
+    report
+}
@@ -0,0 +1,410 @@
+use crate::config::{ConfigError, Value};
+use crate::Snapshot;
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// How a sink writes a snapshot.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// The whole snapshot as one JSON object on one line.
+    Json,
+    /// InfluxDB line protocol, one line per measurement with nanosecond
+    /// timestamps.
+    Influx,
+    /// Graphite plaintext with tags (Graphite 1.1), one line per value with
+    /// second timestamps, under `tcl.`.
+    Graphite,
+}
+
+impl Encoding {
+    pub fn from_config(format: &Value<String>) -> Result<Self, ConfigError> {
+        match format.value.as_str() {
+            "json" => Ok(Encoding::Json),
+            "influx" => Ok(Encoding::Influx),
+            "graphite" => Ok(Encoding::Graphite),
+            other => Err(format.error(format!(
+                "unknown sink format `{}` (expected json, influx or graphite)",
+                other
+            ))),
+        }
+    }
+
+    /// The `Content-Type` of an HTTP request carrying [`Encoding::encode`].
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::Influx | Encoding::Graphite => "text/plain; charset=utf-8",
+        }
+    }
+
+    /// The snapshot as newline-terminated lines.
+    pub fn encode(self, snapshot: &Snapshot) -> serde_json::Result<Vec<u8>> {
+        let text = match self {
+            Encoding::Json => {
+                let mut line = serde_json::to_vec(snapshot)?;
+                line.push(b'\n');
+                return Ok(line);
+            }
+            Encoding::Influx => influx(snapshot),
+            Encoding::Graphite => graphite(snapshot),
+        };
+        Ok(text.into_bytes())
+    }
+}
+
+enum Field {
+    Integer(i64),
+    Float(f64),
+}
+
+/// One measurement: the values one collector reported for one disk, interface,
+/// core and so on. Every point is tagged with the host name and the host's
+/// labels; a label named after one of the point's own tags is left out, so
+/// no key appears twice.
+struct Point<'a> {
+    measurement: &'static str,
+    tags: Vec<(&'a str, Cow<'a, str>)>,
+    /// Unmeasured values are left out, as are NaN and infinities, which
+    /// neither protocol can carry.
+    fields: Vec<(&'static str, Field)>,
+}
+
+impl<'a> Point<'a> {
+    fn new(
+        host: &[(&'a str, Cow<'a, str>)],
+        measurement: &'static str,
+        tags: Vec<(&'a str, Cow<'a, str>)>,
+    ) -> Self {
+        let mut all: Vec<_> = host
+            .iter()
+            .filter(|(key, _)| !tags.iter().any(|(tag, _)| tag == key))
+            .cloned()
+            .collect();
+        all.extend(tags);
+        Point {
+            measurement,
+            tags: all,
+            fields: Vec::new(),
+        }
+    }
+
+    fn push_float(&mut self, name: &'static str, value: Option<f64>) {
+        if let Some(value) = value.filter(|v| v.is_finite()) {
+            self.fields.push((name, Field::Float(value)));
+        }
+    }
+
+    fn push_int(&mut self, name: &'static str, value: u64) {
+        self.fields.push((name, Field::Integer(value as i64)));
+    }
+}
+
+fn points(snapshot: &Snapshot) -> Vec<Point<'_>> {
+    let system = &snapshot.system;
+    // A `host` label replaces the host name, as it does in `otlp::resource`.
+    let mut host: Vec<(&str, Cow<str>)> = vec![("host", Cow::from(&system.system_host_name))];
+    for (key, value) in &system.labels {
+        host.retain(|(k, _)| k != key);
+        host.push((key.as_str(), Cow::from(value)));
+    }
+    let point = |measurement, tags| Point::new(&host, measurement, tags);
+
+    let mut points = Vec::new();
+
+    let mut p = point("system", Vec::new());
+    p.push_int("uptime", system.uptime);
+    p.push_int("boot_time", system.boot_time);
+    p.push_int("total_memory_bytes", system.total_memory_bytes);
+    points.push(p);
+
+    for disk in &snapshot.disks {
+        let mut p = point(
+            "disk",
+            vec![
+                ("mount_point", Cow::from(&disk.mount_point)),
+                ("device", Cow::from(&disk.name)),
+                ("file_system", Cow::from(&disk.file_system)),
+                ("kind", Cow::from(&disk.kind)),
+            ],
+        );
+        p.push_int("total_bytes", disk.total_bytes);
+        p.push_int("available_bytes", disk.available_bytes);
+        p.push_int("used_bytes", disk.used_bytes);
+        p.push_float(
+            "used_percent",
+            (disk.total_bytes > 0)
+                .then(|| 100.0 * disk.used_bytes as f64 / disk.total_bytes as f64),
+        );
+        points.push(p);
+    }
+
+    if let Some(cpu) = &snapshot.cpu {
+        let mut p = point("cpu", Vec::new());
+        p.push_float("usage_percent", Some(f64::from(cpu.usage_percent)));
+        p.push_int("frequency_mhz", cpu.frequency_mhz);
+        p.push_float("load_one", Some(cpu.load_one));
+        p.push_float("load_five", Some(cpu.load_five));
+        p.push_float("load_fifteen", Some(cpu.load_fifteen));
+        points.push(p);
+
+        for core in &cpu.cores {
+            let mut p = point("cpu_core", vec![("core", Cow::from(&core.name))]);
+            p.push_float("usage_percent", Some(f64::from(core.usage_percent)));
+            p.push_int("frequency_mhz", core.frequency_mhz);
+            points.push(p);
+        }
+    }
+
+    if let Some(memory) = &snapshot.memory {
+        let mut p = point("memory", Vec::new());
+        p.push_int("total_bytes", memory.total_bytes);
+        p.push_int("used_bytes", memory.used_bytes);
+        p.push_int("free_bytes", memory.free_bytes);
+        p.push_int("available_bytes", memory.available_bytes);
+        p.push_int("swap_total_bytes", memory.swap_total_bytes);
+        p.push_int("swap_used_bytes", memory.swap_used_bytes);
+        p.push_int("swap_free_bytes", memory.swap_free_bytes);
+        points.push(p);
+    }
+
+    for network in &snapshot.networks {
+        let mut p = point("network", vec![("interface", Cow::from(&network.name))]);
+        p.push_int("received_bytes", network.received_bytes);
+        p.push_int("transmitted_bytes", network.transmitted_bytes);
+        p.push_int("packets_received", network.packets_received);
+        p.push_int("packets_transmitted", network.packets_transmitted);
+        p.push_int("errors_received", network.errors_received);
+        p.push_int("errors_transmitted", network.errors_transmitted);
+        p.push_float("receive_rate", network.receive_rate);
+        p.push_float("transmit_rate", network.transmit_rate);
+        p.push_float("packets_received_rate", network.packets_received_rate);
+        p.push_float("packets_transmitted_rate", network.packets_transmitted_rate);
+        points.push(p);
+    }
+
+    for sensor in &snapshot.sensors {
+        let mut p = point("sensor", vec![("sensor", Cow::from(&sensor.label))]);
+        p.push_float("temperature", sensor.temperature.map(f64::from));
+        p.push_float("max", sensor.max.map(f64::from));
+        p.push_float("critical", sensor.critical.map(f64::from));
+        points.push(p);
+    }
+
+    for process in snapshot.unique_processes() {
+        let mut p = point(
+            "process",
+            vec![
+                ("pid", Cow::from(process.pid.to_string())),
+                ("name", Cow::from(&process.name)),
+            ],
+        );
+        p.push_float("cpu_percent", Some(f64::from(process.cpu_percent)));
+        p.push_int("memory_bytes", process.memory_bytes);
+        p.push_int("disk_read_bytes", process.disk_read_bytes);
+        p.push_int("disk_written_bytes", process.disk_written_bytes);
+        points.push(p);
+    }
+
+    points.retain(|p| !p.fields.is_empty());
+    points
+}
+
+/// InfluxDB line protocol. Tags with empty values are left out, since the
+/// protocol does not allow them.
+fn influx(snapshot: &Snapshot) -> String {
+    let timestamp = snapshot.collected_at * 1_000_000;
+    let mut out = String::new();
+    for point in points(snapshot) {
+        out.push_str(&influx_escape(point.measurement, ", "));
+        for (key, value) in point.tags.iter().filter(|(_, v)| !v.is_empty()) {
+            // Writing to a String cannot fail.
+            let _ = write!(
+                out,
+                ",{}={}",
+                influx_escape(key, ",= "),
+                influx_escape(value, ",= ")
+            );
+        }
+        for (i, (name, value)) in point.fields.iter().enumerate() {
+            out.push(if i == 0 { ' ' } else { ',' });
+            let _ = match value {
+                Field::Integer(n) => write!(out, "{}={}i", name, n),
+                Field::Float(f) => write!(out, "{}={}", name, f),
+            };
+        }
+        let _ = writeln!(out, " {}", timestamp);
+    }
+    out
+}
+
+fn influx_escape<'s>(text: &'s str, special: &str) -> Cow<'s, str> {
+    if !text.contains(|c| special.contains(c) || c == '\\' || c == '\n') {
+        return Cow::from(text);
+    }
+    let mut escaped = String::with_capacity(text.len() + 4);
+    for c in text.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            c if special.contains(c) || c == '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    Cow::from(escaped)
+}
+
+/// Graphite plaintext with tags, e.g.
+/// `tcl.disk.used_bytes;host=web1;mount_point=/ 1234 1717243200`.
+fn graphite(snapshot: &Snapshot) -> String {
+    let timestamp = snapshot.collected_at.div_euclid(1000);
+    let mut out = String::new();
+    for point in points(snapshot) {
+        let mut tags = String::new();
+        for (key, value) in point.tags.iter().filter(|(_, v)| !v.is_empty()) {
+            let _ = write!(tags, ";{}={}", graphite_tag(key), graphite_tag(value));
+        }
+        for (name, value) in &point.fields {
+            let _ = write!(out, "tcl.{}.{}{} ", point.measurement, name, tags);
+            let _ = match value {
+                Field::Integer(n) => writeln!(out, "{} {}", n, timestamp),
+                Field::Float(f) => writeln!(out, "{} {}", f, timestamp),
+            };
+        }
+    }
+    out
+}
+
+/// Replaces what a Graphite tag cannot contain with `_`.
+fn graphite_tag(text: &str) -> Cow<'_, str> {
+    let invalid = |c: char| c.is_whitespace() || ";~=!^".contains(c);
+    if text.contains(invalid) {
+        Cow::from(text.replace(invalid, "_"))
+    } else {
+        Cow::from(text)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// One disk, a label, and a process that ranks in both top lists.
+    pub(crate) fn snapshot() -> Snapshot {
+        let process = |sort_key, rank| {
+            json!({
+                "sort_key": sort_key, "rank": rank, "pid": 42, "name": "nginx",
+                "cmd": "nginx -g daemon off;", "user": "www", "memory_bytes": 4096,
+                "cpu_percent": 1.5, "disk_read_bytes": 0, "disk_written_bytes": 8,
+                "start_time": 1_717_240_000,
+            })
+        };
+        serde_json::from_value(json!({
+            "id": "00000000-0000-0000-0000-000000000001",
+            "collected_at": 1_717_243_200_000i64,
+            "system": {
+                "machine_id": "m1", "system_name": "Debian", "system_host_name": "web 1",
+                "kernel_version": "6.1", "os_version": "12", "long_os_version": "Debian 12",
+                "distribution_id": "debian", "cpu_arch": "x86_64", "boot_time": 1_717_243_100,
+                "uptime": 100, "total_memory_bytes": 2048, "labels": { "env": "prod" },
+            },
+            "disks": [{
+                "name": "/dev/sda1", "mount_point": "/", "file_system": "ext4", "kind": "SSD",
+                "total_bytes": 1000, "available_bytes": 250, "used_bytes": 750,
+            }],
+            "cpu": null,
+            "memory": null,
+            "networks": [],
+            "processes": [process("cpu", 1), process("memory", 1)],
+            "sensors": [],
+        }))
+        .expect("valid snapshot")
+    }
+
+    #[test]
+    fn escapes_influx_text() {
+        assert!(matches!(influx_escape("cpu", ", "), Cow::Borrowed("cpu")));
+        assert_eq!(influx_escape("a b,c", ", "), r"a\ b\,c");
+        assert_eq!(influx_escape("k=v", ",= "), r"k\=v");
+        assert_eq!(influx_escape(r"C:\", ",= "), r"C:\\");
+        assert_eq!(influx_escape("two\nlines", ", "), r"two\nlines");
+    }
+
+    #[test]
+    fn cleans_graphite_tags() {
+        assert!(matches!(
+            graphite_tag("/var/log"),
+            Cow::Borrowed("/var/log")
+        ));
+        assert_eq!(graphite_tag("web 1"), "web_1");
+        assert_eq!(graphite_tag("a;b~c=d!e^f\tg"), "a_b_c_d_e_f_g");
+    }
+
+    #[test]
+    fn encodes_influx_lines() {
+        let text = String::from_utf8(Encoding::Influx.encode(&snapshot()).unwrap()).unwrap();
+        assert_eq!(
+            text,
+            "system,host=web\\ 1,env=prod uptime=100i,boot_time=1717243100i,total_memory_bytes=2048i 1717243200000000000\n\
+             disk,host=web\\ 1,env=prod,mount_point=/,device=/dev/sda1,file_system=ext4,kind=SSD total_bytes=1000i,available_bytes=250i,used_bytes=750i,used_percent=75 1717243200000000000\n\
+             process,host=web\\ 1,env=prod,pid=42,name=nginx cpu_percent=1.5,memory_bytes=4096i,disk_read_bytes=0i,disk_written_bytes=8i 1717243200000000000\n"
+        );
+    }
+
+    #[test]
+    fn encodes_graphite_lines() {
+        let text = String::from_utf8(Encoding::Graphite.encode(&snapshot()).unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3 + 4 + 4);
+        assert_eq!(
+            lines[0],
+            "tcl.system.uptime;host=web_1;env=prod 100 1717243200"
+        );
+        assert_eq!(
+            lines[6],
+            "tcl.disk.used_percent;host=web_1;env=prod;mount_point=/;device=/dev/sda1;file_system=ext4;kind=SSD 75 1717243200"
+        );
+        assert_eq!(
+            lines[7],
+            "tcl.process.cpu_percent;host=web_1;env=prod;pid=42;name=nginx 1.5 1717243200"
+        );
+    }
+
+    #[test]
+    fn never_repeats_a_tag_key() {
+        let mut snapshot = snapshot();
+        for key in ["host", "mount_point", "pid", "name"] {
+            snapshot
+                .system
+                .labels
+                .insert(key.to_string(), "label".to_string());
+        }
+        let text = String::from_utf8(Encoding::Influx.encode(&snapshot).unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[1],
+            "disk,env=prod,host=label,name=label,pid=label,mount_point=/,device=/dev/sda1,file_system=ext4,kind=SSD total_bytes=1000i,available_bytes=250i,used_bytes=750i,used_percent=75 1717243200000000000"
+        );
+        assert!(lines[2]
+            .starts_with("process,env=prod,host=label,mount_point=label,pid=42,name=nginx "));
+        for line in lines {
+            let tags = line.split(' ').next().unwrap().split(',').skip(1);
+            let keys: Vec<&str> = tags.map(|t| t.split('=').next().unwrap()).collect();
+            let mut unique = keys.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(keys.len(), unique.len(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn encodes_json_as_one_line() {
+        let bytes = Encoding::Json.encode(&snapshot()).unwrap();
+        assert_eq!(bytes.iter().filter(|&&b| b == b'\n').count(), 1);
+        assert!(bytes.ends_with(b"\n"));
+        let back: Snapshot = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(back.processes.len(), 2);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::config::ConfigError;
+use std::fmt;
+use std::io;
+
+/// Everything that can stop the agent, grouped by what an operator has to do
+/// about it. Each kind maps to its own process exit code (see [`Error::exit_code`]),
+/// following the `sysexits.h` conventions.
+#[derive(Debug)]
+pub enum Error {
+    /// Invalid or missing settings. Exit code 78.
+    Config(String),
+    /// The database could not be opened or reached. Exit code 69.
+    Connection(libsql::Error),
+    /// Creating or upgrading the schema failed, or a command that does not
+    /// migrate found it out of date. Exit code 65.
+    Schema(SchemaError),
+    /// Gathering metrics from the host failed. Exit code 71.
+    Collection(String),
+    /// The database rejected a snapshot; it has been spooled for retry. Exit code 75.
+    Write(libsql::Error),
+    /// Reading recorded history back failed. Exit code 70.
+    Query(libsql::Error),
+    /// A network sink could not deliver a snapshot. Exit code 76.
+    Delivery { sink: String, message: String },
+    /// Local I/O such as the state directory or signal handlers failed. Exit code 74.
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum SchemaError {
+    /// A migration, or reading the schema version, failed.
+    Migration(libsql::Error),
+    /// The database is at `version`, older than the `latest` this build reads.
+    Outdated { version: i64, latest: i64 },
+}
+
+impl Error {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Config(_) => 78,
+            Error::Connection(_) => 69,
+            Error::Schema(_) => 65,
+            Error::Collection(_) => 71,
+            Error::Write(_) => 75,
+            Error::Query(_) => 70,
+            Error::Delivery { .. } => 76,
+            Error::Io(_) => 74,
+        }
+    }
+
+    /// Wraps a libsql error from a schema operation, unless it is really a
+    /// failure to reach the database.
+    pub fn schema(e: libsql::Error) -> Self {
+        if is_connection_error(&e) {
+            Error::Connection(e)
+        } else {
+            Error::Schema(SchemaError::Migration(e))
+        }
+    }
+
+    /// Wraps a libsql error from an insert, unless it is really a failure to
+    /// reach the database.
+    pub fn write(e: libsql::Error) -> Self {
+        if is_connection_error(&e) {
+            Error::Connection(e)
+        } else {
+            Error::Write(e)
+        }
+    }
+
+    /// Wraps a libsql error from a read, unless it is really a failure to
+    /// reach the database.
+    pub fn query(e: libsql::Error) -> Self {
+        if is_connection_error(&e) {
+            Error::Connection(e)
+        } else {
+            Error::Query(e)
+        }
+    }
+}
+
+/// Whether a libsql error means the server could not be talked to, rather than
+/// that it refused a statement.
+fn is_connection_error(e: &libsql::Error) -> bool {
+    matches!(
+        e,
+        libsql::Error::ConnectionFailed(_)
+            | libsql::Error::Hrana(_)
+            | libsql::Error::WriteDelegation(_)
+            | libsql::Error::Replication(_)
+            | libsql::Error::InvalidTlsConfiguration(_)
+    )
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(msg) => write!(f, "configuration error: {}", msg),
+            Error::Connection(e) => write!(f, "database unreachable: {}", e),
+            Error::Schema(SchemaError::Migration(e)) => write!(f, "schema migration failed: {}", e),
+            Error::Schema(SchemaError::Outdated { version, latest }) => write!(
+                f,
+                "database schema is at version {} but this build needs {}; run `tcl migrate`",
+                version, latest
+            ),
+            Error::Collection(msg) => write!(f, "collection failed: {}", msg),
+            Error::Write(e) => write!(f, "write failed, snapshot spooled: {}", e),
+            Error::Query(e) => write!(f, "query failed: {}", e),
+            Error::Delivery { sink, message } => {
+                write!(f, "could not deliver to {}: {}", sink, message)
+            }
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connection(e)
+            | Error::Schema(SchemaError::Migration(e))
+            | Error::Write(e)
+            | Error::Query(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Config(_)
+            | Error::Collection(_)
+            | Error::Schema(SchemaError::Outdated { .. })
+            | Error::Delivery { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e.to_string())
+    }
+}
+
+impl From<Vec<ConfigError>> for Error {
+    fn from(errors: Vec<ConfigError>) -> Self {
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        Error::Config(messages.join("; "))
+    }
+}
@@ -0,0 +1,359 @@
+use crate::error::{Error, Result};
+use crate::output::{cell, json_value, write_csv_row, Format};
+use crate::query::Filter;
+use libsql::Connection;
+use parquet::basic::{Compression, LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::MilliSeconds;
+use parquet::schema::types::Type;
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Tables that can be exported. Every one but `runs` is joined to its run for
+/// the collection time and host.
+pub const TABLES: &[&str] = &[
+    "runs",
+    "disks",
+    "cpus",
+    "cpu_cores",
+    "memory",
+    "network_interfaces",
+    "processes",
+    "sensors",
+];
+
+/// Rows are read this many at a time, so neither a local nor a remote
+/// database ever hands over the whole table at once. Each page becomes one
+/// Parquet row group.
+const PAGE_SIZE: usize = 10_000;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Integer,
+    Real,
+    Text,
+    /// UTC milliseconds since the Unix epoch.
+    Timestamp,
+}
+
+struct Column {
+    name: String,
+    kind: Kind,
+}
+
+/// Streams the rows of `table` recorded within `filter` to `out`, oldest
+/// first, returning how many were written.
+///
+/// `collected_at` is kept as UTC milliseconds in CSV and JSON, and stored as
+/// a millisecond timestamp in Parquet.
+pub async fn export(
+    conn: &Connection,
+    table: &str,
+    filter: &Filter,
+    format: Format,
+    out: Box<dyn Write + Send>,
+) -> Result<u64> {
+    if !TABLES.contains(&table) {
+        return Err(Error::Config(format!(
+            "cannot export `{}` (expected one of {})",
+            table,
+            TABLES.join(", ")
+        )));
+    }
+
+    let (key, select, from) = if table == "runs" {
+        (
+            "r.id",
+            "h.host_name AS host, r.*",
+            "runs r LEFT JOIN hosts h ON h.id = r.host_id".to_string(),
+        )
+    } else {
+        (
+            "m.id",
+            "r.collected_at, h.host_name AS host, r.machine_id, m.*",
+            format!(
+                "{} m JOIN runs r ON r.id = m.run_id LEFT JOIN hosts h ON h.id = r.host_id",
+                table
+            ),
+        )
+    };
+
+    let mut columns = vec![Column {
+        name: "host".to_string(),
+        kind: Kind::Text,
+    }];
+    if table != "runs" {
+        columns.insert(
+            0,
+            Column {
+                name: "collected_at".to_string(),
+                kind: Kind::Timestamp,
+            },
+        );
+        columns.push(Column {
+            name: "machine_id".to_string(),
+            kind: Kind::Text,
+        });
+    }
+    columns.extend(table_columns(conn, table).await?);
+    // `runs` declares its own `collected_at` as an integer; type it like the
+    // one joined onto the other tables.
+    for column in &mut columns {
+        if column.name == "collected_at" {
+            column.kind = Kind::Timestamp;
+        }
+    }
+
+    let mut writer = RowWriter::new(format, &columns, out)?;
+    let mut last_id = 0;
+    let mut written = 0;
+    loop {
+        let mut params = Vec::new();
+        let mut clauses = filter.clauses(&mut params);
+        params.push(last_id.into());
+        clauses.push(format!("{} > ?{}", key, params.len()));
+        let sql = format!(
+            "SELECT {key}, {select} FROM {from} WHERE {} ORDER BY {key} LIMIT {PAGE_SIZE}",
+            clauses.join(" AND ")
+        );
+
+        let mut rows = conn.query(&sql, params).await.map_err(Error::query)?;
+        let mut page = Vec::with_capacity(PAGE_SIZE);
+        while let Some(row) = rows.next().await.map_err(Error::query)? {
+            last_id = row.get::<i64>(0).map_err(Error::query)?;
+            let values = (1..=columns.len())
+                .map(|i| row.get_value(i as i32))
+                .collect::<libsql::Result<Vec<_>>>()
+                .map_err(Error::query)?;
+            page.push(values);
+        }
+
+        writer.write(&columns, &page)?;
+        written += page.len() as u64;
+        if page.len() < PAGE_SIZE {
+            break;
+        }
+    }
+    writer.finish()?;
+    Ok(written)
+}
+
+/// The declared columns of `table`, typed by SQLite's affinity rules.
+async fn table_columns(conn: &Connection, table: &str) -> Result<Vec<Column>> {
+    let mut rows = conn
+        .query(
+            "SELECT name, type FROM pragma_table_info(?1) ORDER BY cid",
+            [table],
+        )
+        .await
+        .map_err(Error::query)?;
+
+    let mut columns = Vec::new();
+    while let Some(row) = rows.next().await.map_err(Error::query)? {
+        let name: String = row.get(0).map_err(Error::query)?;
+        let declared = row.get::<String>(1).map_err(Error::query)?.to_uppercase();
+        let kind = if declared.contains("INT") {
+            Kind::Integer
+        } else if ["REAL", "FLOA", "DOUB"]
+            .iter()
+            .any(|t| declared.contains(t))
+        {
+            Kind::Real
+        } else {
+            Kind::Text
+        };
+        columns.push(Column { name, kind });
+    }
+    Ok(columns)
+}
+
+enum RowWriter {
+    Csv(Box<dyn Write + Send>),
+    /// A single JSON array, written one element at a time.
+    Json {
+        out: Box<dyn Write + Send>,
+        first: bool,
+    },
+    Jsonl(Box<dyn Write + Send>),
+    Parquet(SerializedFileWriter<Box<dyn Write + Send>>),
+}
+
+impl RowWriter {
+    fn new(format: Format, columns: &[Column], mut out: Box<dyn Write + Send>) -> Result<Self> {
+        match format {
+            Format::Csv => {
+                write_csv_row(&mut out, columns.iter().map(|c| c.name.as_str()))?;
+                Ok(RowWriter::Csv(out))
+            }
+            Format::Json => {
+                out.write_all(b"[")?;
+                Ok(RowWriter::Json { out, first: true })
+            }
+            Format::Jsonl => Ok(RowWriter::Jsonl(out)),
+            Format::Parquet => {
+                let fields = columns
+                    .iter()
+                    .map(|column| parquet_field(column).map(Arc::new))
+                    .collect::<parquet::errors::Result<Vec<_>>>()
+                    .map_err(parquet_error)?;
+                let schema = Type::group_type_builder("snapshot")
+                    .with_fields(fields)
+                    .build()
+                    .map_err(parquet_error)?;
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                SerializedFileWriter::new(out, Arc::new(schema), Arc::new(properties))
+                    .map(RowWriter::Parquet)
+                    .map_err(parquet_error)
+            }
+            Format::Table => Err(Error::Config(
+                "export writes csv, json, jsonl or parquet, not table".to_string(),
+            )),
+        }
+    }
+
+    fn write(&mut self, columns: &[Column], rows: &[Vec<libsql::Value>]) -> Result<()> {
+        match self {
+            RowWriter::Csv(out) => {
+                for row in rows {
+                    let cells: Vec<String> =
+                        row.iter().map(|v| cell(&json_value(v.clone()))).collect();
+                    write_csv_row(out, cells.iter().map(String::as_str))?;
+                }
+            }
+            RowWriter::Json { out, first } => {
+                for row in rows {
+                    out.write_all(if *first { b"\n" } else { b",\n" })?;
+                    *first = false;
+                    serde_json::to_writer(&mut *out, &json_object(columns, row))
+                        .map_err(io::Error::from)?;
+                }
+            }
+            RowWriter::Jsonl(out) => {
+                for row in rows {
+                    serde_json::to_writer(&mut *out, &json_object(columns, row))
+                        .map_err(io::Error::from)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+            RowWriter::Parquet(writer) => {
+                if !rows.is_empty() {
+                    write_row_group(writer, columns, rows).map_err(parquet_error)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            RowWriter::Csv(mut out) | RowWriter::Jsonl(mut out) => out.flush()?,
+            RowWriter::Json { mut out, first } => {
+                out.write_all(if first { b"]\n" } else { b"\n]\n" })?;
+                out.flush()?;
+            }
+            RowWriter::Parquet(writer) => {
+                writer.into_inner().map_err(parquet_error)?.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn json_object(columns: &[Column], row: &[libsql::Value]) -> Map<String, Value> {
+    columns
+        .iter()
+        .zip(row)
+        .map(|(column, value)| (column.name.clone(), json_value(value.clone())))
+        .collect()
+}
+
+/// Every column is optional, since SQLite lets any value be NULL.
+fn parquet_field(column: &Column) -> parquet::errors::Result<Type> {
+    let (physical, logical) = match column.kind {
+        Kind::Integer => (PhysicalType::INT64, None),
+        Kind::Real => (PhysicalType::DOUBLE, None),
+        Kind::Text => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+        Kind::Timestamp => (
+            PhysicalType::INT64,
+            Some(LogicalType::Timestamp {
+                is_adjusted_to_u_t_c: true,
+                unit: TimeUnit::MILLIS(MilliSeconds {}),
+            }),
+        ),
+    };
+    Type::primitive_type_builder(&column.name, physical)
+        .with_repetition(Repetition::OPTIONAL)
+        .with_logical_type(logical)
+        .build()
+}
+
+/// Writes one page of rows as a row group, column by column. A value whose
+/// storage class does not match the column's declared type is converted.
+fn write_row_group(
+    writer: &mut SerializedFileWriter<Box<dyn Write + Send>>,
+    columns: &[Column],
+    rows: &[Vec<libsql::Value>],
+) -> parquet::errors::Result<()> {
+    let mut group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = group.next_column()? {
+        let values = rows.iter().map(|row| &row[index]);
+        let levels: Vec<i16> = values
+            .clone()
+            .map(|v| i16::from(!matches!(v, libsql::Value::Null)))
+            .collect();
+
+        match columns[index].kind {
+            Kind::Integer | Kind::Timestamp => {
+                let data: Vec<i64> = values
+                    .filter_map(|v| match v {
+                        libsql::Value::Integer(n) => Some(*n),
+                        libsql::Value::Real(f) => Some(*f as i64),
+                        libsql::Value::Text(s) => Some(s.parse().unwrap_or_default()),
+                        libsql::Value::Blob(_) => Some(0),
+                        libsql::Value::Null => None,
+                    })
+                    .collect();
+                column
+                    .typed::<Int64Type>()
+                    .write_batch(&data, Some(&levels), None)?;
+            }
+            Kind::Real => {
+                let data: Vec<f64> = values
+                    .filter_map(|v| match v {
+                        libsql::Value::Integer(n) => Some(*n as f64),
+                        libsql::Value::Real(f) => Some(*f),
+                        libsql::Value::Text(s) => Some(s.parse().unwrap_or(f64::NAN)),
+                        libsql::Value::Blob(_) => Some(f64::NAN),
+                        libsql::Value::Null => None,
+                    })
+                    .collect();
+                column
+                    .typed::<DoubleType>()
+                    .write_batch(&data, Some(&levels), None)?;
+            }
+            Kind::Text => {
+                let data: Vec<ByteArray> = values
+                    .filter(|v| !matches!(v, libsql::Value::Null))
+                    .map(|v| ByteArray::from(cell(&json_value(v.clone())).into_bytes()))
+                    .collect();
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&data, Some(&levels), None)?;
+            }
+        }
+        column.close()?;
+        index += 1;
+    }
+    group.close()?;
+    Ok(())
+}
+
+fn parquet_error(e: parquet::errors::ParquetError) -> Error {
+    Error::Io(io::Error::other(e))
+}
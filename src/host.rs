@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use sysinfo::System;
+use uuid::Uuid;
+
+const MACHINE_ID_PATH: &str = "/etc/machine-id";
+const HOST_ID_FILE: &str = "host-id";
+
+/// Identity of the machine a snapshot was taken on, upserted into `hosts`.
+#[derive(Serialize, Deserialize)]
+pub struct SystemInfo {
+    /// Stable identifier that survives renames; see [`machine_id`].
+    pub machine_id: String,
+    pub system_name: String,
+    pub system_host_name: String,
+    pub kernel_version: String,
+    pub os_version: String,
+    pub long_os_version: String,
+    pub distribution_id: String,
+    pub cpu_arch: String,
+    /// Seconds since the Unix epoch.
+    pub boot_time: u64,
+    /// Seconds since boot.
+    pub uptime: u64,
+    pub total_memory_bytes: u64,
+    /// Labels from the config file, stored with the host.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Returns the contents of `/etc/machine-id`, or a UUID generated on first use
+/// and kept in the state directory when the machine has none.
+pub fn machine_id(state_dir: &Path) -> io::Result<String> {
+    if let Ok(id) = fs::read_to_string(MACHINE_ID_PATH) {
+        let id = id.trim();
+        if !id.is_empty() {
+            return Ok(id.to_string());
+        }
+    }
+
+    let path = state_dir.join(HOST_ID_FILE);
+    if let Ok(id) = fs::read_to_string(&path) {
+        let id = id.trim();
+        if !id.is_empty() {
+            return Ok(id.to_string());
+        }
+    }
+
+    let id = Uuid::new_v4().to_string();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, format!("{}\n", id))?;
+    Ok(id)
+}
+
+/// Builds the host record. Expects memory to have been refreshed on `sys`.
+pub fn get_system_info(
+    sys: &System,
+    machine_id: &str,
+    labels: &BTreeMap<String, String>,
+) -> SystemInfo {
+    SystemInfo {
+        machine_id: machine_id.to_string(),
+        system_name: System::name().unwrap_or_default(),
+        system_host_name: System::host_name().unwrap_or_default(),
+        kernel_version: System::kernel_version().unwrap_or_default(),
+        os_version: System::os_version().unwrap_or_default(),
+        long_os_version: System::long_os_version().unwrap_or_default(),
+        distribution_id: System::distribution_id(),
+        cpu_arch: System::cpu_arch().unwrap_or_default(),
+        boot_time: System::boot_time(),
+        uptime: System::uptime(),
+        total_memory_bytes: sys.total_memory(),
+        labels: labels.clone(),
+    }
+}
@@ -0,0 +1,55 @@
+use crate::config::{ConfigError, LogConfig, Origin};
+use tracing_subscriber::EnvFilter;
+
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "unknown log format `{}` (expected text or json)",
+                other
+            )),
+        }
+    }
+
+    pub fn from_config(log: &LogConfig) -> Result<Self, ConfigError> {
+        LogFormat::parse(&log.format.value).map_err(|e| log.format.error(e))
+    }
+}
+
+/// Parses the `[log]` level, an `EnvFilter` directive such as `info` or
+/// `tcl=debug`.
+pub fn filter(log: &LogConfig) -> Result<EnvFilter, ConfigError> {
+    EnvFilter::try_new(&log.level.value).map_err(|e| {
+        log.level
+            .error(format!("invalid log filter `{}`: {}", log.level.value, e))
+    })
+}
+
+/// Installs the global tracing subscriber, writing to stderr.
+///
+/// The level and format come from `[log]`, which the environment sets from
+/// `TCL_LOG` and `TCL_LOG_FORMAT` and the command line from `--log-level` and
+/// `--log-format`.
+pub fn init(log: &LogConfig) -> Result<(), ConfigError> {
+    let filter = filter(log)?;
+    let format = LogFormat::from_config(log)?;
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    };
+    result.map_err(|e| ConfigError {
+        origin: Origin::Default,
+        message: format!("could not install logger: {}", e),
+    })
+}
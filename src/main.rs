@@ -1,117 +1,500 @@
+mod cli;
+mod config;
+mod cpu;
+mod daemon;
+mod db;
+mod disk;
+mod doctor;
+mod encoding;
+mod error;
+mod export;
+mod host;
+mod logging;
+mod memory;
+mod migrations;
+mod network;
+mod otlp;
+mod output;
+mod process;
+mod prometheus;
+mod query;
+mod sensors;
+mod sink;
+mod spool;
+mod state;
+mod timestamp;
+
+use clap::Parser;
+use cli::{Cli, Command, ConfigCommand, ExportArgs, QueryArgs, WindowArgs};
+use config::{CollectorsConfig, Config, ConfigError};
+use cpu::{get_cpu_info, CpuInfo, SAMPLE_WINDOW};
+use daemon::DaemonOptions;
+use db::init_db;
+use disk::{get_disk_info, DiskInfo, DiskSelection};
 use dotenv::dotenv;
-use libsql::{Builder, Connection};
-use std::env;
+use error::{Error, Result};
+use host::{get_system_info, machine_id, SystemInfo};
+use memory::{get_memory_info, MemoryInfo};
+use network::{InterfaceFilter, NetworkCollector, NetworkInfo};
+use output::{Format, Table};
+use process::{ProcessCollector, ProcessInfo, ProcessOptions};
+use query::Mode;
+use sensors::{SensorCollector, SensorInfo};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sink::Sinks;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::process::ExitCode;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use sysinfo::{Disks, System};
+use timestamp::{parse_duration, parse_time};
+use tracing::{debug, error, info};
+use uuid::Uuid;
 
-struct SystemInfo<'a> {
-    system_name: &'a str,
-    system_host_name: &'a str,
+/// Everything gathered in one collection cycle.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Idempotency key, so a snapshot replayed from the spool is only stored once.
+    pub id: String,
+    /// UTC milliseconds since the Unix epoch, taken when collection started.
+    pub collected_at: i64,
+    pub system: SystemInfo,
+    pub disks: Vec<DiskInfo>,
+    /// `None` when the CPU collector is disabled.
+    pub cpu: Option<CpuInfo>,
+    /// `None` when the memory collector is disabled.
+    pub memory: Option<MemoryInfo>,
+    pub networks: Vec<NetworkInfo>,
+    pub processes: Vec<ProcessInfo>,
+    pub sensors: Vec<SensorInfo>,
 }
 
-struct DiskInfo {
-    system_total_space: f64,
-    system_available_space: f64,
-    system_used_space: f64,
+impl Snapshot {
+    /// The top processes, each once even if it ranks in both top lists.
+    pub fn unique_processes(&self) -> impl Iterator<Item = &ProcessInfo> {
+        let mut seen = BTreeSet::new();
+        self.processes.iter().filter(move |p| seen.insert(p.pid))
+    }
 }
 
-impl DiskInfo {
-    fn bytes_to_gb(bytes: u64) -> f64 {
-        bytes as f64 / 1024f64 / 1024f64 / 1024f64
+/// Holds the sysinfo handles so they can be refreshed across daemon cycles.
+pub struct Collector {
+    sys: System,
+    disks: Disks,
+    sensors: SensorCollector,
+    enabled: CollectorsConfig,
+    selection: DiskSelection,
+    networks: NetworkCollector,
+    processes: Option<ProcessCollector>,
+    machine_id: String,
+    labels: BTreeMap<String, String>,
+}
+
+impl Collector {
+    fn new(config: &Config, machine_id: String) -> std::result::Result<Self, Vec<ConfigError>> {
+        Ok(Collector {
+            sys: System::new(),
+            disks: Disks::new(),
+            sensors: SensorCollector::new(),
+            enabled: config.collectors,
+            selection: DiskSelection::from_config(&config.disks)?,
+            networks: NetworkCollector::new(InterfaceFilter::from_config(&config.networks)?),
+            processes: ProcessOptions::from_config(config)?.map(ProcessCollector::new),
+            machine_id,
+            labels: config.labels.clone(),
+        })
+    }
+
+    /// Takes one of the two samples that CPU and process usage are computed from.
+    fn refresh_sampled(&mut self) {
+        self.sys.refresh_cpu();
+        if self.processes.is_some() {
+            ProcessCollector::refresh(&mut self.sys);
+        }
+    }
+
+    pub async fn collect(&mut self) -> Snapshot {
+        let started = Instant::now();
+        let collected_at = now_millis();
+        if self.enabled.cpu || self.processes.is_some() {
+            self.refresh_sampled();
+            tokio::time::sleep(SAMPLE_WINDOW).await;
+            self.refresh_sampled();
+        }
+
+        let cpu = self
+            .enabled
+            .cpu
+            .then(|| timed("cpu", || get_cpu_info(&self.sys)));
+        let processes = timed("processes", || match self.processes.as_mut() {
+            Some(collector) => collector.collect(&self.sys),
+            None => Vec::new(),
+        });
+        let memory = if self.enabled.memory {
+            Some(timed("memory", || get_memory_info(&mut self.sys)))
+        } else {
+            // The host record still reports total memory.
+            self.sys.refresh_memory();
+            None
+        };
+        let system = timed("host", || {
+            get_system_info(&self.sys, &self.machine_id, &self.labels)
+        });
+        let disks = if self.enabled.disks {
+            timed("disks", || {
+                self.disks.refresh_list();
+                get_disk_info(&self.disks, &self.selection)
+            })
+        } else {
+            Vec::new()
+        };
+        let networks = if self.enabled.networks {
+            timed("networks", || self.networks.collect())
+        } else {
+            Vec::new()
+        };
+        let sensors = if self.enabled.sensors {
+            timed("sensors", || self.sensors.collect())
+        } else {
+            Vec::new()
+        };
+
+        info!(
+            disks = disks.len(),
+            cores = cpu.as_ref().map_or(0, |cpu| cpu.cores.len()),
+            interfaces = networks.len(),
+            processes = processes.len(),
+            sensors = sensors.len(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "collected snapshot"
+        );
+
+        Snapshot {
+            id: Uuid::new_v4().to_string(),
+            collected_at,
+            system,
+            disks,
+            cpu,
+            memory,
+            networks,
+            processes,
+            sensors,
+        }
     }
 }
 
+/// Runs one collector, logging how long it took.
+fn timed<T>(collector: &'static str, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    debug!(
+        collector,
+        elapsed_ms = started.elapsed().as_secs_f64() * 1000.0,
+        "collector finished"
+    );
+    result
+}
+
+/// Current UTC time in milliseconds since the Unix epoch.
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
 #[tokio::main]
-async fn main() -> Result<(), libsql::Error> {
-    let conn = init_db().await.unwrap();
+async fn main() -> ExitCode {
+    dotenv().ok();
+
+    let cli = Cli::parse();
+    let config = match Config::load(&cli.overrides()) {
+        Ok(config) => config,
+        Err(e) => return config_error(e.to_string()),
+    };
+
+    if let Some(Command::Config {
+        command: ConfigCommand::Validate,
+    }) = &cli.command
+    {
+        return match validate_config(&config, cli.global.format) {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::from(e.exit_code())
+            }
+        };
+    }
+    if let Err(e) = logging::init(&config.log) {
+        return config_error(e.to_string());
+    }
 
-    let mut sys = System::new_all();
-    sys.refresh_all();
+    match run(cli, config).await {
+        Ok(code) => code,
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            error!(exit_code = e.exit_code(), "{}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+/// Reports a configuration error before logging is set up.
+fn config_error(message: String) -> ExitCode {
+    let e = Error::Config(message);
+    eprintln!("Error: {}", e);
+    ExitCode::from(e.exit_code())
+}
+
+/// Prints every configuration error, each prefixed with where the setting came from.
+fn validate_config(config: &Config, format: Option<Format>) -> Result<ExitCode> {
+    let errors = config.validate();
+    let source = match &config.path {
+        Some(path) => path.display().to_string(),
+        None => "defaults and environment".to_string(),
+    };
+    let failed = Error::Config(format!("{}: {} error(s)", source, errors.len()));
+    if format == Some(Format::Parquet) {
+        return Err(parquet_unsupported());
+    }
+
+    let mut out = io::stdout().lock();
+    match format {
+        None => {
+            for e in &errors {
+                writeln!(out, "{}", e)?;
+            }
+            if errors.is_empty() {
+                writeln!(out, "{}: ok", source)?;
+            } else {
+                writeln!(out, "{}", failed)?;
+            }
+        }
+        Some(format) => {
+            let mut table = Table::new(["origin", "error"]);
+            for e in &errors {
+                table.push(vec![
+                    Value::from(e.origin.to_string()),
+                    Value::from(e.message.as_str()),
+                ]);
+            }
+            table.write(format, &mut out)?;
+        }
+    }
 
-    let system_name = System::name().unwrap_or_default().to_string();
-    let system_host_name = System::host_name().unwrap_or_default().to_string();
+    if errors.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::from(failed.exit_code()))
+    }
+}
 
-    let system_info = SystemInfo {
-        system_name: system_name.as_str(),
-        system_host_name: system_host_name.as_str(),
+async fn run(cli: Cli, config: Config) -> Result<ExitCode> {
+    let format = cli.global.format;
+    let command = match cli.command {
+        None if cli.global.daemon => Command::Daemon {
+            interval: None,
+            metrics_listen: None,
+        },
+        Some(command) if cli.global.daemon && !matches!(command, Command::Daemon { .. }) => {
+            return Err(Error::Config(
+                "--daemon cannot be combined with another subcommand".to_string(),
+            ));
+        }
+        command => command.unwrap_or(Command::Collect),
     };
+    if format == Some(Format::Parquet) && !matches!(command, Command::Export(_)) {
+        return Err(parquet_unsupported());
+    }
+    match command {
+        Command::Collect => return collect(&config, format).await,
+        Command::Daemon { .. } => {
+            if let Some(format) = format {
+                return Err(Error::Config(format!(
+                    "daemon does not print results, so --format {} has no effect",
+                    format.as_str()
+                )));
+            }
+            let options = DaemonOptions::from_config(&config.daemon)?;
+            let mut sinks = Sinks::open(&config).await?;
+            let mut collector = new_collector(&config)?;
+            info!(
+                config = config.path.as_ref().map(|p| p.display().to_string()),
+                interval_secs = options.interval.as_secs(),
+                jitter_secs = options.jitter.as_secs(),
+                metrics_listen = options.metrics_listen.map(|a| a.to_string()),
+                "starting daemon"
+            );
+            daemon::run(&mut sinks, &mut collector, &options).await?;
+        }
+        Command::Migrate => {
+            let mut db = init_db(&config).await?;
+            let before = db.schema_version().await?;
+            db.ensure_schema().await?;
+            let after = db.schema_version().await?;
+
+            let mut table = Table::new(["from_version", "to_version", "applied"]);
+            table.push(vec![
+                Value::from(before),
+                Value::from(after),
+                Value::from(after - before),
+            ]);
+            table.write(format.unwrap_or(Format::Table), &mut io::stdout().lock())?;
+        }
+        Command::Query(args) => {
+            let table = query(&config, &args).await?;
+            table.write(format.unwrap_or(Format::Table), &mut io::stdout().lock())?;
+        }
+        Command::Export(args) => export(&config, &args, format).await?,
+        Command::Doctor => {
+            let report = doctor::run(&config).await;
+            report
+                .table
+                .write(format.unwrap_or(Format::Table), &mut io::stdout().lock())?;
+            if let Some(e) = report.failure {
+                return Ok(ExitCode::from(e.exit_code()));
+            }
+        }
+        Command::Config { .. } => unreachable!("handled before logging is set up"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-    let disks = Disks::new_with_refreshed_list();
-    let get_disk_info = get_disk_info(disks);
+async fn query(config: &Config, args: &QueryArgs) -> Result<Table> {
+    if args.list_metrics {
+        return Ok(query::metric_list());
+    }
 
-    let mut disk_info = DiskInfo {
-        system_total_space: 0.0,
-        system_available_space: 0.0,
-        system_used_space: 0.0,
+    let filter = filter(&args.window)?;
+    let mode = match &args.bucket {
+        Some(bucket) => match parse_duration(bucket) {
+            Ok(d) if !d.is_zero() => Mode::Series(d),
+            Ok(_) => {
+                return Err(Error::Config(
+                    "--bucket must be longer than zero".to_string(),
+                ))
+            }
+            Err(e) => return Err(Error::Config(format!("--bucket: {}", e))),
+        },
+        None if args.stats => Mode::Stats,
+        None => Mode::Latest,
     };
+    let metrics = query::select_metrics(&args.metrics).map_err(Error::Config)?;
 
-    if let Some(d) = get_disk_info {
-        let system_used_space = d.system_used_space;
-        let system_total_space = d.system_total_space;
-        let system_available_space = d.system_available_space;
+    let db = init_db(config).await?;
+    db.check_schema().await?;
+    let table = if metrics.is_empty() {
+        query::hosts(db.conn(), &filter).await
+    } else {
+        query::metrics(db.conn(), &metrics, &filter, &mode).await
+    };
+    table.map_err(Error::query)
+}
 
-        disk_info = DiskInfo {
-            system_total_space,
-            system_available_space,
-            system_used_space,
-        };
+/// Streams one table to `--output` or stdout.
+async fn export(config: &Config, args: &ExportArgs, format: Option<Format>) -> Result<()> {
+    let format = match format {
+        Some(format) => format,
+        None => match args
+            .output
+            .as_deref()
+            .and_then(|p| p.extension())
+            .and_then(|e| e.to_str())
+        {
+            Some("json") => Format::Json,
+            Some("jsonl" | "ndjson") => Format::Jsonl,
+            Some("parquet") => Format::Parquet,
+            _ => Format::Csv,
+        },
+    };
+    if format == Format::Table {
+        return Err(Error::Config(
+            "export writes csv, json, jsonl or parquet, not table".to_string(),
+        ));
     }
+    let filter = filter(&args.window)?;
 
-    insert_into_db(&conn, system_info, disk_info).await?;
+    let db = init_db(config).await?;
+    db.check_schema().await?;
+    let out: Box<dyn Write + Send> = match &args.output {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout())),
+    };
+    let rows = export::export(db.conn(), &args.table, &filter, format, out).await?;
+    info!(
+        table = args.table,
+        format = format.as_str(),
+        rows,
+        "export finished"
+    );
     Ok(())
 }
 
-async fn insert_into_db(
-    conn: &Connection,
-    system: SystemInfo<'_>,
-    disk: DiskInfo,
-) -> Result<(), libsql::Error> {
-    conn.execute(
-        "INSERT INTO info (system_name, system_host_name, system_total_space, system_available_space, system_used_space) VALUES (?1, ?2, ?3, ?4, ?5)",
-        [
-            system.system_name,
-            system.system_host_name,
-            disk.system_total_space.to_string().as_str(),
-            disk.system_available_space.to_string().as_str(),
-            disk.system_used_space.to_string().as_str(),
-        ],
-    )
-    .await
-    .unwrap_or_else(|e| {
-        panic!("Error: {:?}", e);
-    });
-    Ok(())
+/// Parses `--host`, `--since` and `--until`.
+fn filter(window: &WindowArgs) -> Result<query::Filter> {
+    let now = now_millis();
+    let time = |flag: &str, value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|v| parse_time(v, now).map_err(|e| Error::Config(format!("{}: {}", flag, e))))
+            .transpose()
+    };
+    Ok(query::Filter {
+        hosts: window.hosts.clone(),
+        since: time("--since", &window.since)?,
+        until: time("--until", &window.until)?,
+    })
 }
 
-async fn init_db() -> Result<Connection, libsql::Error> {
-    dotenv().ok();
+fn parquet_unsupported() -> Error {
+    Error::Config("only `tcl export` writes parquet".to_string())
+}
 
-    let url = env::var("LIBSQL_URL").expect("LIBSQL_URL must be set");
-    let token = env::var("LIBSQL_AUTH_TOKEN").unwrap_or_default();
-
-    let db = Builder::new_remote(url, token).build().await?;
-    let conn = db.connect().unwrap();
-    Ok(conn)
-}
-
-fn get_disk_info(disks: Disks) -> Option<DiskInfo> {
-    disks.iter().find_map(|disk| {
-        if let Some(name) = disk.name().to_str() {
-            if name.contains("1p6")
-                && disk
-                    .mount_point()
-                    .to_str()
-                    .unwrap_or_else(|| panic!("Error getting mount point"))
-                    .contains("/home")
-            {
-                let total_usage = disk.total_space() - disk.available_space();
-
-                return Some(DiskInfo {
-                    system_total_space: DiskInfo::bytes_to_gb(disk.total_space()),
-                    system_available_space: DiskInfo::bytes_to_gb(disk.available_space()),
-                    system_used_space: DiskInfo::bytes_to_gb(total_usage),
-                });
-            }
+fn new_collector(config: &Config) -> Result<Collector> {
+    let machine_id = machine_id(&config.state_dir)
+        .map_err(|e| Error::Collection(format!("could not determine host id: {}", e)))?;
+    Ok(Collector::new(config, machine_id)?)
+}
+
+/// Collects one snapshot and writes it to every sink, printing it when a
+/// format is given. Sink failures are logged as they happen; the command exits
+/// with the first one's code.
+async fn collect(config: &Config, format: Option<Format>) -> Result<ExitCode> {
+    let mut sinks = Sinks::open(config).await?;
+    let mut collector = new_collector(config)?;
+    let snapshot = collector.collect().await;
+    let written = sinks.write(&snapshot).await;
+
+    let mut out = io::stdout().lock();
+    match format {
+        None => {}
+        Some(Format::Json) => {
+            serde_json::to_writer_pretty(&mut out, &snapshot).map_err(io::Error::from)?;
+            writeln!(out)?;
         }
-        None
-    })
+        Some(Format::Jsonl) => {
+            serde_json::to_writer(&mut out, &snapshot).map_err(io::Error::from)?;
+            writeln!(out)?;
+        }
+        Some(format) => {
+            let mut table =
+                Table::new(["run", "host", "disks", "interfaces", "processes", "sensors"]);
+            table.push(vec![
+                Value::from(snapshot.id.as_str()),
+                Value::from(snapshot.system.system_host_name.as_str()),
+                Value::from(snapshot.disks.len()),
+                Value::from(snapshot.networks.len()),
+                Value::from(snapshot.unique_processes().count()),
+                Value::from(snapshot.sensors.len()),
+            ]);
+            table.write(format, &mut out)?;
+        }
+    }
+
+    let synced = sinks.sync().await;
+    match written.and(synced) {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(e) => Ok(ExitCode::from(e.exit_code())),
+    }
 }
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+use sysinfo::System;
+
+#[derive(Serialize, Deserialize)]
+pub struct MemoryInfo {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub free_bytes: u64,
+    pub available_bytes: u64,
+    pub swap_total_bytes: u64,
+    pub swap_used_bytes: u64,
+    pub swap_free_bytes: u64,
+}
+
+pub fn get_memory_info(sys: &mut System) -> MemoryInfo {
+    sys.refresh_memory();
+
+    MemoryInfo {
+        total_bytes: sys.total_memory(),
+        used_bytes: sys.used_memory(),
+        free_bytes: sys.free_memory(),
+        available_bytes: sys.available_memory(),
+        swap_total_bytes: sys.total_swap(),
+        swap_used_bytes: sys.used_swap(),
+        swap_free_bytes: sys.free_swap(),
+    }
+}
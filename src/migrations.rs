@@ -0,0 +1,488 @@
+use libsql::{params, Connection};
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+}
+
+/// Every schema change, in order. Append new entries; never edit applied ones.
+///
+/// Version 1 matches the `info` table older databases were created with by
+/// hand, and version 2 the tables the agent used to create on its own, so both
+/// use `IF NOT EXISTS` to adopt existing databases without touching their data.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create info table",
+        sql: "
+CREATE TABLE IF NOT EXISTS info (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    system_name TEXT,
+    system_host_name TEXT,
+    system_total_space REAL,
+    system_available_space REAL,
+    system_used_space REAL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+",
+    },
+    Migration {
+        version: 2,
+        description: "create runs and disks tables",
+        sql: "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    system_name TEXT NOT NULL,
+    system_host_name TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE IF NOT EXISTS disks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    name TEXT NOT NULL,
+    mount_point TEXT NOT NULL,
+    file_system TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    total_bytes INTEGER NOT NULL,
+    available_bytes INTEGER NOT NULL,
+    used_bytes INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS disks_run_id ON disks (run_id);
+",
+    },
+    Migration {
+        version: 3,
+        description: "add runs.collected_at and disk_usage view",
+        sql: "
+ALTER TABLE runs ADD COLUMN collected_at INTEGER;
+UPDATE runs SET collected_at = CAST(strftime('%s', created_at) AS INTEGER) * 1000
+    WHERE collected_at IS NULL;
+CREATE INDEX runs_collected_at ON runs (collected_at);
+CREATE VIEW disk_usage AS
+SELECT
+    runs.id AS run_id,
+    runs.collected_at,
+    runs.system_host_name,
+    disks.name,
+    disks.mount_point,
+    disks.file_system,
+    disks.kind,
+    disks.total_bytes / 1073741824.0 AS total_gb,
+    disks.available_bytes / 1073741824.0 AS available_gb,
+    disks.used_bytes / 1073741824.0 AS used_gb,
+    100.0 * disks.used_bytes / NULLIF(disks.total_bytes, 0) AS used_percent
+FROM disks
+JOIN runs ON runs.id = disks.run_id;
+",
+    },
+    Migration {
+        version: 4,
+        description: "create cpus and cpu_cores tables",
+        sql: "
+CREATE TABLE cpus (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL UNIQUE REFERENCES runs (id),
+    brand TEXT NOT NULL,
+    physical_cores INTEGER,
+    logical_cores INTEGER NOT NULL,
+    usage_percent REAL NOT NULL,
+    frequency_mhz INTEGER NOT NULL,
+    load_one REAL NOT NULL,
+    load_five REAL NOT NULL,
+    load_fifteen REAL NOT NULL
+);
+CREATE TABLE cpu_cores (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    name TEXT NOT NULL,
+    usage_percent REAL NOT NULL,
+    frequency_mhz INTEGER NOT NULL
+);
+CREATE INDEX cpu_cores_run_id ON cpu_cores (run_id);
+",
+    },
+    Migration {
+        version: 5,
+        description: "create memory table",
+        sql: "
+CREATE TABLE memory (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL UNIQUE REFERENCES runs (id),
+    total_bytes INTEGER NOT NULL,
+    used_bytes INTEGER NOT NULL,
+    free_bytes INTEGER NOT NULL,
+    available_bytes INTEGER NOT NULL,
+    swap_total_bytes INTEGER NOT NULL,
+    swap_used_bytes INTEGER NOT NULL,
+    swap_free_bytes INTEGER NOT NULL
+);
+",
+    },
+    Migration {
+        version: 6,
+        description: "create network_interfaces table",
+        sql: "
+CREATE TABLE network_interfaces (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    name TEXT NOT NULL,
+    received_bytes INTEGER NOT NULL,
+    transmitted_bytes INTEGER NOT NULL,
+    packets_received INTEGER NOT NULL,
+    packets_transmitted INTEGER NOT NULL,
+    errors_received INTEGER NOT NULL,
+    errors_transmitted INTEGER NOT NULL,
+    receive_rate REAL,
+    transmit_rate REAL,
+    packets_received_rate REAL,
+    packets_transmitted_rate REAL
+);
+CREATE INDEX network_interfaces_run_id ON network_interfaces (run_id);
+",
+    },
+    Migration {
+        version: 7,
+        description: "create processes table",
+        sql: "
+CREATE TABLE processes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    sort_key TEXT NOT NULL,
+    rank INTEGER NOT NULL,
+    pid INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    cmd TEXT NOT NULL,
+    user TEXT,
+    memory_bytes INTEGER NOT NULL,
+    cpu_percent REAL NOT NULL,
+    disk_read_bytes INTEGER NOT NULL,
+    disk_written_bytes INTEGER NOT NULL,
+    start_time INTEGER NOT NULL
+);
+CREATE INDEX processes_run_id ON processes (run_id);
+",
+    },
+    Migration {
+        version: 8,
+        description: "create sensors table",
+        sql: "
+CREATE TABLE sensors (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    label TEXT NOT NULL,
+    temperature REAL,
+    max REAL,
+    critical REAL
+);
+CREATE INDEX sensors_run_id ON sensors (run_id);
+",
+    },
+    Migration {
+        version: 9,
+        description: "move host identity from runs into hosts",
+        sql: "
+CREATE TABLE hosts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    host_name TEXT NOT NULL,
+    system_name TEXT NOT NULL,
+    kernel_version TEXT NOT NULL DEFAULT '',
+    os_version TEXT NOT NULL DEFAULT '',
+    long_os_version TEXT NOT NULL DEFAULT '',
+    distribution_id TEXT NOT NULL DEFAULT '',
+    cpu_arch TEXT NOT NULL DEFAULT '',
+    boot_time INTEGER,
+    uptime INTEGER,
+    total_memory_bytes INTEGER,
+    first_seen_at INTEGER NOT NULL,
+    last_seen_at INTEGER NOT NULL
+);
+CREATE UNIQUE INDEX hosts_host_name ON hosts (host_name);
+INSERT INTO hosts (host_name, system_name, first_seen_at, last_seen_at)
+    SELECT system_host_name, system_name, MIN(collected_at), MAX(collected_at)
+    FROM runs GROUP BY system_host_name;
+ALTER TABLE runs ADD COLUMN host_id INTEGER REFERENCES hosts (id);
+UPDATE runs SET host_id = (SELECT id FROM hosts WHERE hosts.host_name = runs.system_host_name);
+CREATE INDEX runs_host_id ON runs (host_id);
+DROP VIEW disk_usage;
+ALTER TABLE runs DROP COLUMN system_name;
+ALTER TABLE runs DROP COLUMN system_host_name;
+CREATE VIEW disk_usage AS
+SELECT
+    runs.id AS run_id,
+    runs.collected_at,
+    hosts.host_name,
+    disks.name,
+    disks.mount_point,
+    disks.file_system,
+    disks.kind,
+    disks.total_bytes / 1073741824.0 AS total_gb,
+    disks.available_bytes / 1073741824.0 AS available_gb,
+    disks.used_bytes / 1073741824.0 AS used_gb,
+    100.0 * disks.used_bytes / NULLIF(disks.total_bytes, 0) AS used_percent
+FROM disks
+JOIN runs ON runs.id = disks.run_id
+JOIN hosts ON hosts.id = runs.host_id;
+",
+    },
+    Migration {
+        version: 10,
+        description: "key hosts by machine id and track host name history",
+        sql: "
+DROP INDEX hosts_host_name;
+CREATE INDEX hosts_host_name ON hosts (host_name);
+ALTER TABLE hosts ADD COLUMN machine_id TEXT;
+CREATE UNIQUE INDEX hosts_machine_id ON hosts (machine_id);
+ALTER TABLE runs ADD COLUMN machine_id TEXT;
+CREATE TABLE host_names (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    host_id INTEGER NOT NULL REFERENCES hosts (id),
+    host_name TEXT NOT NULL,
+    first_seen_at INTEGER NOT NULL,
+    last_seen_at INTEGER NOT NULL
+);
+CREATE INDEX host_names_host_id ON host_names (host_id);
+INSERT INTO host_names (host_id, host_name, first_seen_at, last_seen_at)
+    SELECT id, host_name, first_seen_at, last_seen_at FROM hosts;
+",
+    },
+    Migration {
+        version: 11,
+        description: "add runs.idempotency_key",
+        sql: "
+ALTER TABLE runs ADD COLUMN idempotency_key TEXT;
+CREATE UNIQUE INDEX runs_idempotency_key ON runs (idempotency_key);
+",
+    },
+    Migration {
+        version: 12,
+        description: "add hosts.labels",
+        sql: "
+ALTER TABLE hosts ADD COLUMN labels TEXT;
+",
+    },
+    Migration {
+        version: 13,
+        description: "copy info rows into runs and disks",
+        // `info` held one disk per row in GiB, with no name or mount point. The
+        // copied runs are keyed `info-<id>`, and `info` itself is kept.
+        sql: "
+UPDATE hosts SET first_seen_at = MIN(first_seen_at, (
+    SELECT CAST(strftime('%s', MIN(created_at)) AS INTEGER) * 1000
+    FROM info WHERE COALESCE(info.system_host_name, '') = hosts.host_name
+))
+WHERE EXISTS (SELECT 1 FROM info WHERE COALESCE(info.system_host_name, '') = hosts.host_name);
+UPDATE host_names SET first_seen_at = (SELECT first_seen_at FROM hosts WHERE hosts.id = host_names.host_id)
+WHERE id IN (SELECT MIN(id) FROM host_names GROUP BY host_id)
+    AND host_name = (SELECT host_name FROM hosts WHERE hosts.id = host_names.host_id)
+    AND first_seen_at > (SELECT first_seen_at FROM hosts WHERE hosts.id = host_names.host_id);
+INSERT INTO hosts (host_name, system_name, first_seen_at, last_seen_at)
+    SELECT
+        COALESCE(system_host_name, ''),
+        COALESCE(MAX(system_name), ''),
+        CAST(strftime('%s', MIN(created_at)) AS INTEGER) * 1000,
+        CAST(strftime('%s', MAX(created_at)) AS INTEGER) * 1000
+    FROM info
+    WHERE COALESCE(system_host_name, '') NOT IN (SELECT host_name FROM hosts)
+    GROUP BY COALESCE(system_host_name, '');
+INSERT INTO host_names (host_id, host_name, first_seen_at, last_seen_at)
+    SELECT id, host_name, first_seen_at, last_seen_at FROM hosts
+    WHERE id NOT IN (SELECT host_id FROM host_names);
+INSERT INTO runs (idempotency_key, created_at, collected_at, host_id)
+    SELECT
+        'info-' || info.id,
+        info.created_at,
+        CAST(strftime('%s', info.created_at) AS INTEGER) * 1000,
+        (SELECT id FROM hosts WHERE hosts.host_name = COALESCE(info.system_host_name, '')
+            ORDER BY first_seen_at LIMIT 1)
+    FROM info
+    ORDER BY info.id;
+INSERT INTO disks (run_id, name, mount_point, file_system, kind, total_bytes, available_bytes, used_bytes)
+    SELECT
+        runs.id, '', '', '', 'unknown',
+        CAST(ROUND(COALESCE(info.system_total_space, 0) * 1073741824) AS INTEGER),
+        CAST(ROUND(COALESCE(info.system_available_space, 0) * 1073741824) AS INTEGER),
+        CAST(ROUND(COALESCE(info.system_used_space, 0) * 1073741824) AS INTEGER)
+    FROM info
+    JOIN runs ON runs.idempotency_key = 'info-' || info.id;
+",
+    },
+];
+
+/// Returns the schema version recorded in `schema_migrations`, or 0 for a fresh database.
+pub async fn current_version(conn: &Connection) -> Result<i64, libsql::Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        (),
+    )
+    .await?;
+
+    let mut rows = conn
+        .query(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+            (),
+        )
+        .await?;
+    match rows.next().await? {
+        Some(row) => row.get(0),
+        None => Ok(0),
+    }
+}
+
+/// Like [`current_version`], but without creating `schema_migrations`, so it
+/// can be used by read-only checks.
+pub async fn applied_version(conn: &Connection) -> Result<i64, libsql::Error> {
+    let mut rows = conn
+        .query(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'",
+            (),
+        )
+        .await?;
+    if rows.next().await?.is_none() {
+        return Ok(0);
+    }
+    drop(rows);
+    current_version(conn).await
+}
+
+/// The version the newest migration brings the schema to.
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Brings the schema up to the newest migration, applying each pending migration
+/// in its own transaction. Returns the version the database ends up at.
+pub async fn migrate(conn: &Connection) -> Result<i64, libsql::Error> {
+    let current = current_version(conn).await?;
+    let mut version = current;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction().await?;
+        tx.execute_batch(migration.sql).await?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, description) VALUES (?1, ?2)",
+            params![migration.version, migration.description],
+        )
+        .await?;
+        tx.commit().await?;
+
+        version = migration.version;
+    }
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsql::Builder;
+
+    async fn rows(conn: &Connection, sql: &str) -> Vec<Vec<String>> {
+        let mut rows = conn.query(sql, ()).await.unwrap();
+        let columns = rows.column_count();
+        let mut out = Vec::new();
+        while let Some(row) = rows.next().await.unwrap() {
+            let values = (0..columns)
+                .map(|i| match row.get_value(i).unwrap() {
+                    libsql::Value::Null => "NULL".to_string(),
+                    libsql::Value::Integer(n) => n.to_string(),
+                    libsql::Value::Real(f) => f.to_string(),
+                    libsql::Value::Text(s) => s,
+                    libsql::Value::Blob(_) => "<blob>".to_string(),
+                })
+                .collect();
+            out.push(values);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn copies_baseline_info_rows_once() {
+        let database = Builder::new_local(":memory:").build().await.unwrap();
+        let conn = database.connect().unwrap();
+        // What the first release created and wrote, with no schema_migrations.
+        conn.execute_batch(
+            "CREATE TABLE info (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                system_name TEXT,
+                system_host_name TEXT,
+                system_total_space REAL,
+                system_available_space REAL,
+                system_used_space REAL,
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            INSERT INTO info (system_name, system_host_name, system_total_space, system_available_space, system_used_space, created_at) VALUES
+                ('Ubuntu', 'alpha', 100.5, 40.25, 60.25, '2024-01-01 10:00:00'),
+                ('Ubuntu', 'alpha', 100.5, 39, 61.5, '2024-01-02 10:00:00'),
+                ('Debian', 'beta', 50, 10, 40, '2024-02-01 00:00:00');",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(migrate(&conn).await.unwrap(), latest_version());
+
+        let hosts =
+            "SELECT id, host_name, system_name, first_seen_at, last_seen_at FROM hosts ORDER BY id";
+        let host_names =
+            "SELECT host_id, host_name, first_seen_at, last_seen_at FROM host_names ORDER BY id";
+        let runs = "SELECT id, idempotency_key, collected_at, host_id FROM runs ORDER BY id";
+        let disks =
+            "SELECT run_id, kind, total_bytes, available_bytes, used_bytes FROM disks ORDER BY id";
+        let strings = |rows: &[&[&str]]| -> Vec<Vec<String>> {
+            rows.iter()
+                .map(|r| r.iter().map(|v| v.to_string()).collect())
+                .collect()
+        };
+
+        assert_eq!(
+            rows(&conn, hosts).await,
+            strings(&[
+                &["1", "alpha", "Ubuntu", "1704103200000", "1704189600000"],
+                &["2", "beta", "Debian", "1706745600000", "1706745600000"],
+            ])
+        );
+        assert_eq!(
+            rows(&conn, host_names).await,
+            strings(&[
+                &["1", "alpha", "1704103200000", "1704189600000"],
+                &["2", "beta", "1706745600000", "1706745600000"],
+            ])
+        );
+        assert_eq!(
+            rows(&conn, runs).await,
+            strings(&[
+                &["1", "info-1", "1704103200000", "1"],
+                &["2", "info-2", "1704189600000", "1"],
+                &["3", "info-3", "1706745600000", "2"],
+            ])
+        );
+        // GiB back to bytes: 100.5 GiB is 107911053312 bytes.
+        assert_eq!(
+            rows(&conn, disks).await,
+            strings(&[
+                &["1", "unknown", "107911053312", "43218108416", "64692944896"],
+                &["2", "unknown", "107911053312", "41875931136", "66035122176"],
+                &["3", "unknown", "53687091200", "10737418240", "42949672960"],
+            ])
+        );
+
+        let before = [
+            rows(&conn, hosts).await,
+            rows(&conn, host_names).await,
+            rows(&conn, runs).await,
+            rows(&conn, disks).await,
+        ];
+        assert_eq!(migrate(&conn).await.unwrap(), latest_version());
+        let after = [
+            rows(&conn, hosts).await,
+            rows(&conn, host_names).await,
+            rows(&conn, runs).await,
+            rows(&conn, disks).await,
+        ];
+        assert_eq!(before, after);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::config::{parse_filter, ConfigError, FilterConfig};
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Instant;
+use sysinfo::Networks;
+
+/// Include and exclude glob lists matched against interface names, e.g.
+/// `TCL_NETWORK_EXCLUDE=lo,docker*,veth*`.
+///
+/// An interface is recorded when it matches any include pattern (or the include
+/// list is empty) and matches none of the exclude patterns.
+pub struct InterfaceFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl InterfaceFilter {
+    /// Builds the filter from the `[networks]` globs, which the environment
+    /// sets from comma-separated `TCL_NETWORK_INCLUDE` and `TCL_NETWORK_EXCLUDE`.
+    pub fn from_config(filter: &FilterConfig) -> Result<Self, Vec<ConfigError>> {
+        let (include, exclude) = parse_filter(filter, parse_pattern)?;
+        Ok(InterfaceFilter { include, exclude })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|p| p.matches(name));
+        included && !self.exclude.iter().any(|p| p.matches(name))
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Result<Pattern, String> {
+    Pattern::new(pattern).map_err(|e| format!("invalid interface glob `{}`: {}", pattern, e))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NetworkInfo {
+    pub name: String,
+    pub received_bytes: u64,
+    pub transmitted_bytes: u64,
+    pub packets_received: u64,
+    pub packets_transmitted: u64,
+    pub errors_received: u64,
+    pub errors_transmitted: u64,
+    /// Bytes per second since the previous cycle; `None` on an interface's first cycle.
+    pub receive_rate: Option<f64>,
+    pub transmit_rate: Option<f64>,
+    pub packets_received_rate: Option<f64>,
+    pub packets_transmitted_rate: Option<f64>,
+}
+
+/// Keeps the interface counters between cycles so rates can be computed.
+pub struct NetworkCollector {
+    networks: Networks,
+    filter: InterfaceFilter,
+    seen: HashSet<String>,
+    last_refresh: Option<Instant>,
+}
+
+impl NetworkCollector {
+    pub fn new(filter: InterfaceFilter) -> Self {
+        NetworkCollector {
+            networks: Networks::new(),
+            filter,
+            seen: HashSet::new(),
+            last_refresh: None,
+        }
+    }
+
+    pub fn collect(&mut self) -> Vec<NetworkInfo> {
+        self.networks.refresh_list();
+        let now = Instant::now();
+        let elapsed = self
+            .last_refresh
+            .replace(now)
+            .map(|last| now.duration_since(last).as_secs_f64())
+            .filter(|secs| *secs > 0.0);
+
+        let mut interfaces: Vec<NetworkInfo> = self
+            .networks
+            .iter()
+            .filter(|(name, _)| self.filter.matches(name))
+            .map(|(name, data)| {
+                let elapsed = elapsed.filter(|_| self.seen.contains(name));
+                let rate = |delta: u64| elapsed.map(|secs| delta as f64 / secs);
+
+                NetworkInfo {
+                    name: name.clone(),
+                    received_bytes: data.total_received(),
+                    transmitted_bytes: data.total_transmitted(),
+                    packets_received: data.total_packets_received(),
+                    packets_transmitted: data.total_packets_transmitted(),
+                    errors_received: data.total_errors_on_received(),
+                    errors_transmitted: data.total_errors_on_transmitted(),
+                    receive_rate: rate(data.received()),
+                    transmit_rate: rate(data.transmitted()),
+                    packets_received_rate: rate(data.packets_received()),
+                    packets_transmitted_rate: rate(data.packets_transmitted()),
+                }
+            })
+            .collect();
+        interfaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+        self.seen = self.networks.keys().cloned().collect();
+        interfaces
+    }
+}
@@ -0,0 +1,575 @@
+use crate::config::{ConfigError, Value};
+use crate::disk::DiskInfo;
+use crate::Snapshot;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_proto::tonic::common::v1::{any_value, AnyValue, InstrumentationScope, KeyValue};
+use opentelemetry_proto::tonic::metrics::v1::number_data_point::Value as Number;
+use opentelemetry_proto::tonic::metrics::v1::{
+    metric, AggregationTemporality, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics,
+    Sum,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+
+/// How an `otlp` sink talks to the collector.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The `MetricsService/Export` RPC.
+    Grpc,
+    /// A protobuf-encoded request POSTed to the metrics URL.
+    HttpProtobuf,
+}
+
+impl Protocol {
+    pub fn from_config(protocol: &Value<String>) -> Result<Self, ConfigError> {
+        match protocol.value.as_str() {
+            "grpc" => Ok(Protocol::Grpc),
+            "http/protobuf" => Ok(Protocol::HttpProtobuf),
+            other => Err(protocol.error(format!(
+                "unknown otlp protocol `{}` (expected grpc or http/protobuf)",
+                other
+            ))),
+        }
+    }
+
+    /// Where a collector on this machine listens by default.
+    pub fn default_url(self) -> &'static str {
+        match self {
+            Protocol::Grpc => "http://localhost:4317",
+            Protocol::HttpProtobuf => "http://localhost:4318/v1/metrics",
+        }
+    }
+}
+
+/// The snapshot's disk, CPU and memory values as OTLP metrics, named after the
+/// OpenTelemetry `system.*` semantic conventions. The host is described by
+/// resource attributes rather than by an attribute on every point.
+pub fn request(snapshot: &Snapshot) -> ExportMetricsServiceRequest {
+    let mut m = Metrics {
+        // Sums count from boot, like the collector's hostmetrics receiver.
+        start: snapshot.system.boot_time.saturating_mul(1_000_000_000),
+        time: u64::try_from(snapshot.collected_at).unwrap_or(0) * 1_000_000,
+        metrics: Vec::new(),
+    };
+
+    let filesystem = |disk: &DiskInfo| {
+        vec![
+            string("system.device", &disk.name),
+            string("system.filesystem.mountpoint", &disk.mount_point),
+            string("system.filesystem.type", &disk.file_system),
+        ]
+    };
+    let with = |mut attributes: Vec<KeyValue>, extra: KeyValue| {
+        attributes.push(extra);
+        attributes
+    };
+    m.sum(
+        "system.filesystem.usage",
+        "By",
+        "Filesystem space by state.",
+        snapshot.disks.iter().flat_map(|d| {
+            let reserved = d
+                .total_bytes
+                .saturating_sub(d.used_bytes)
+                .saturating_sub(d.available_bytes);
+            [
+                ("used", d.used_bytes),
+                ("free", d.available_bytes),
+                ("reserved", reserved),
+            ]
+            .map(|(state, bytes)| {
+                (
+                    with(filesystem(d), string("system.filesystem.state", state)),
+                    int(bytes),
+                )
+            })
+        }),
+    );
+    m.sum(
+        "system.filesystem.limit",
+        "By",
+        "Size of the filesystem.",
+        snapshot
+            .disks
+            .iter()
+            .map(|d| (filesystem(d), int(d.total_bytes))),
+    );
+    m.gauge(
+        "system.filesystem.utilization",
+        "1",
+        "Fraction of the filesystem in use.",
+        snapshot
+            .disks
+            .iter()
+            .filter(|d| d.total_bytes > 0)
+            .map(|d| {
+                (
+                    filesystem(d),
+                    Number::AsDouble(d.used_bytes as f64 / d.total_bytes as f64),
+                )
+            }),
+    );
+
+    if let Some(cpu) = &snapshot.cpu {
+        let logical = |n: usize| vec![int_attribute("cpu.logical_number", n as u64)];
+        m.gauge(
+            "system.cpu.utilization",
+            "1",
+            "Fraction of time each logical CPU was busy.",
+            cpu.cores.iter().enumerate().map(|(n, core)| {
+                (
+                    logical(n),
+                    Number::AsDouble(f64::from(core.usage_percent) / 100.0),
+                )
+            }),
+        );
+        m.gauge(
+            "system.cpu.frequency",
+            "Hz",
+            "Frequency of each logical CPU.",
+            cpu.cores
+                .iter()
+                .enumerate()
+                .map(|(n, core)| (logical(n), int(core.frequency_mhz * 1_000_000))),
+        );
+        m.sum(
+            "system.cpu.logical.count",
+            "{cpu}",
+            "Logical CPUs.",
+            [(Vec::new(), int(cpu.logical_cores as u64))],
+        );
+        m.sum(
+            "system.cpu.physical.count",
+            "{cpu}",
+            "Physical CPU cores.",
+            cpu.physical_cores
+                .map(|cores| (Vec::new(), int(cores as u64))),
+        );
+        for (name, description, value) in [
+            (
+                "system.cpu.load_average.1m",
+                "One-minute load average.",
+                cpu.load_one,
+            ),
+            (
+                "system.cpu.load_average.5m",
+                "Five-minute load average.",
+                cpu.load_five,
+            ),
+            (
+                "system.cpu.load_average.15m",
+                "Fifteen-minute load average.",
+                cpu.load_fifteen,
+            ),
+        ] {
+            m.gauge(
+                name,
+                "{thread}",
+                description,
+                [(Vec::new(), Number::AsDouble(value))],
+            );
+        }
+    }
+
+    if let Some(memory) = &snapshot.memory {
+        let state = |state| vec![string("system.memory.state", state)];
+        m.sum(
+            "system.memory.usage",
+            "By",
+            "Memory in use and free.",
+            [
+                (state("used"), int(memory.used_bytes)),
+                (state("free"), int(memory.free_bytes)),
+            ],
+        );
+        m.sum(
+            "system.memory.limit",
+            "By",
+            "Installed memory.",
+            [(Vec::new(), int(memory.total_bytes))],
+        );
+        if memory.total_bytes > 0 {
+            let total = memory.total_bytes as f64;
+            m.gauge(
+                "system.memory.utilization",
+                "1",
+                "Fraction of memory in use and free.",
+                [
+                    (
+                        state("used"),
+                        Number::AsDouble(memory.used_bytes as f64 / total),
+                    ),
+                    (
+                        state("free"),
+                        Number::AsDouble(memory.free_bytes as f64 / total),
+                    ),
+                ],
+            );
+        }
+        if cfg!(target_os = "linux") {
+            m.sum(
+                "system.linux.memory.available",
+                "By",
+                "Memory available without swapping.",
+                [(Vec::new(), int(memory.available_bytes))],
+            );
+        }
+        let paging = |state| vec![string("system.paging.state", state)];
+        m.sum(
+            "system.paging.usage",
+            "By",
+            "Swap space in use and free.",
+            [
+                (paging("used"), int(memory.swap_used_bytes)),
+                (paging("free"), int(memory.swap_free_bytes)),
+            ],
+        );
+    }
+
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(resource(snapshot)),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    attributes: Vec::new(),
+                    dropped_attributes_count: 0,
+                }),
+                metrics: m.metrics,
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        }],
+    }
+}
+
+/// Host attributes from [`crate::host::SystemInfo`] under their semantic
+/// convention names, followed by the host's labels. A label with the same key
+/// as a convention attribute, such as `service.name`, replaces it.
+fn resource(snapshot: &Snapshot) -> Resource {
+    let system = &snapshot.system;
+    let mut attributes = vec![
+        string("service.name", env!("CARGO_PKG_NAME")),
+        string("service.version", env!("CARGO_PKG_VERSION")),
+        string("host.name", &system.system_host_name),
+        string("host.id", &system.machine_id),
+        string("host.arch", host_arch(&system.cpu_arch)),
+        string("os.type", os_type()),
+        string("os.name", &system.system_name),
+        string("os.version", &system.os_version),
+        string("os.description", &system.long_os_version),
+    ];
+    if let Some(cpu) = &snapshot.cpu {
+        attributes.push(string("host.cpu.model.name", &cpu.brand));
+    }
+    for (key, value) in &system.labels {
+        attributes.retain(|a| &a.key != key);
+        attributes.push(string(key, value));
+    }
+    attributes.retain(|a| {
+        !matches!(&a.value, Some(AnyValue { value: Some(any_value::Value::StringValue(s)) }) if s.is_empty())
+    });
+    Resource {
+        attributes,
+        dropped_attributes_count: 0,
+    }
+}
+
+/// The `host.arch` value for an architecture as `uname -m` names it.
+fn host_arch(arch: &str) -> &str {
+    match arch {
+        "x86_64" | "amd64" => "amd64",
+        "aarch64" | "arm64" => "arm64",
+        "i386" | "i586" | "i686" | "x86" => "x86",
+        "powerpc64" | "ppc64" | "ppc64le" => "ppc64",
+        "powerpc" | "ppc" => "ppc32",
+        arch if arch.starts_with("arm") => "arm32",
+        arch => arch,
+    }
+}
+
+/// The `os.type` value for the system the agent runs on.
+fn os_type() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "darwin",
+        "dragonfly" => "dragonflybsd",
+        os => os,
+    }
+}
+
+/// Collects metrics, leaving out any with no points.
+struct Metrics {
+    start: u64,
+    time: u64,
+    metrics: Vec<Metric>,
+}
+
+impl Metrics {
+    fn points(
+        &self,
+        points: impl IntoIterator<Item = (Vec<KeyValue>, Number)>,
+        start: u64,
+    ) -> Vec<NumberDataPoint> {
+        points
+            .into_iter()
+            .map(|(attributes, value)| NumberDataPoint {
+                attributes,
+                start_time_unix_nano: start,
+                time_unix_nano: self.time,
+                exemplars: Vec::new(),
+                flags: 0,
+                value: Some(value),
+            })
+            .collect()
+    }
+
+    fn push(&mut self, name: &str, unit: &str, description: &str, data: metric::Data) {
+        self.metrics.push(Metric {
+            name: name.to_string(),
+            description: description.to_string(),
+            unit: unit.to_string(),
+            data: Some(data),
+        });
+    }
+
+    fn gauge(
+        &mut self,
+        name: &str,
+        unit: &str,
+        description: &str,
+        points: impl IntoIterator<Item = (Vec<KeyValue>, Number)>,
+    ) {
+        let data_points = self.points(points, 0);
+        if !data_points.is_empty() {
+            self.push(
+                name,
+                unit,
+                description,
+                metric::Data::Gauge(Gauge { data_points }),
+            );
+        }
+    }
+
+    /// A cumulative sum that can go down, such as bytes in use.
+    fn sum(
+        &mut self,
+        name: &str,
+        unit: &str,
+        description: &str,
+        points: impl IntoIterator<Item = (Vec<KeyValue>, Number)>,
+    ) {
+        let data_points = self.points(points, self.start);
+        if !data_points.is_empty() {
+            let sum = Sum {
+                data_points,
+                aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                is_monotonic: false,
+            };
+            self.push(name, unit, description, metric::Data::Sum(sum));
+        }
+    }
+}
+
+fn int(value: u64) -> Number {
+    Number::AsInt(i64::try_from(value).unwrap_or(i64::MAX))
+}
+
+fn string(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+fn int_attribute(key: &str, value: u64) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::IntValue(value as i64)),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::tests::snapshot;
+    use serde_json::json;
+
+    /// The shared fixture plus CPU and memory readings.
+    fn full_snapshot() -> Snapshot {
+        let mut snapshot = snapshot();
+        snapshot.cpu = Some(
+            serde_json::from_value(json!({
+                "brand": "Test CPU", "physical_cores": 1, "logical_cores": 2,
+                "usage_percent": 50.0, "frequency_mhz": 2000,
+                "load_one": 0.5, "load_five": 0.25, "load_fifteen": 0.125,
+                "cores": [
+                    { "name": "cpu0", "usage_percent": 25.0, "frequency_mhz": 2000 },
+                    { "name": "cpu1", "usage_percent": 75.0, "frequency_mhz": 2100 },
+                ],
+            }))
+            .unwrap(),
+        );
+        snapshot.memory = Some(
+            serde_json::from_value(json!({
+                "total_bytes": 1000, "used_bytes": 600, "free_bytes": 400,
+                "available_bytes": 500, "swap_total_bytes": 100,
+                "swap_used_bytes": 10, "swap_free_bytes": 90,
+            }))
+            .unwrap(),
+        );
+        snapshot
+    }
+
+    fn metrics(request: &ExportMetricsServiceRequest) -> &[Metric] {
+        &request.resource_metrics[0].scope_metrics[0].metrics
+    }
+
+    fn points(metric: &Metric) -> &[NumberDataPoint] {
+        match metric.data.as_ref().unwrap() {
+            metric::Data::Gauge(g) => &g.data_points,
+            metric::Data::Sum(s) => &s.data_points,
+            _ => panic!("unexpected data for {}", metric.name),
+        }
+    }
+
+    fn attribute<'a>(attributes: &'a [KeyValue], key: &str) -> Option<&'a str> {
+        attributes.iter().find(|a| a.key == key).map(|a| {
+            match a.value.as_ref().and_then(|v| v.value.as_ref()) {
+                Some(any_value::Value::StringValue(s)) => s.as_str(),
+                other => panic!("{} is not a string: {:?}", key, other),
+            }
+        })
+    }
+
+    #[test]
+    fn names_metrics_after_the_semantic_conventions() {
+        let request = request(&full_snapshot());
+        let names: Vec<(&str, &str)> = metrics(&request)
+            .iter()
+            .map(|m| (m.name.as_str(), m.unit.as_str()))
+            .collect();
+        let mut expected = vec![
+            ("system.filesystem.usage", "By"),
+            ("system.filesystem.limit", "By"),
+            ("system.filesystem.utilization", "1"),
+            ("system.cpu.utilization", "1"),
+            ("system.cpu.frequency", "Hz"),
+            ("system.cpu.logical.count", "{cpu}"),
+            ("system.cpu.physical.count", "{cpu}"),
+            ("system.cpu.load_average.1m", "{thread}"),
+            ("system.cpu.load_average.5m", "{thread}"),
+            ("system.cpu.load_average.15m", "{thread}"),
+            ("system.memory.usage", "By"),
+            ("system.memory.limit", "By"),
+            ("system.memory.utilization", "1"),
+        ];
+        if cfg!(target_os = "linux") {
+            expected.push(("system.linux.memory.available", "By"));
+        }
+        expected.push(("system.paging.usage", "By"));
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn leaves_out_collectors_that_did_not_run() {
+        let request = request(&snapshot());
+        let names: Vec<&str> = metrics(&request).iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "system.filesystem.usage",
+                "system.filesystem.limit",
+                "system.filesystem.utilization"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_filesystem_usage_by_state() {
+        let snapshot = snapshot();
+        let request = request(&snapshot);
+        let usage = points(&metrics(&request)[0]);
+        let states: Vec<(&str, Option<Number>)> = usage
+            .iter()
+            .map(|p| {
+                assert_eq!(
+                    attribute(&p.attributes, "system.filesystem.mountpoint"),
+                    Some("/")
+                );
+                assert_eq!(p.time_unix_nano, 1_717_243_200_000_000_000);
+                assert_eq!(p.start_time_unix_nano, 1_717_243_100_000_000_000);
+                (
+                    attribute(&p.attributes, "system.filesystem.state").unwrap(),
+                    p.value.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            states,
+            [
+                ("used", Some(Number::AsInt(750))),
+                ("free", Some(Number::AsInt(250))),
+                ("reserved", Some(Number::AsInt(0))),
+            ]
+        );
+        // Gauges have no start time.
+        assert_eq!(points(&metrics(&request)[2])[0].start_time_unix_nano, 0);
+    }
+
+    #[test]
+    fn describes_the_host_as_a_resource() {
+        let resource = resource(&full_snapshot());
+        let attributes = &resource.attributes;
+        assert_eq!(
+            attribute(attributes, "service.name"),
+            Some(env!("CARGO_PKG_NAME"))
+        );
+        assert_eq!(attribute(attributes, "host.name"), Some("web 1"));
+        assert_eq!(attribute(attributes, "host.id"), Some("m1"));
+        assert_eq!(attribute(attributes, "host.arch"), Some("amd64"));
+        assert_eq!(attribute(attributes, "os.name"), Some("Debian"));
+        assert_eq!(
+            attribute(attributes, "host.cpu.model.name"),
+            Some("Test CPU")
+        );
+        assert_eq!(attribute(attributes, "env"), Some("prod"));
+    }
+
+    #[test]
+    fn lets_labels_override_and_drops_empty_attributes() {
+        let mut snapshot = snapshot();
+        snapshot.system.machine_id.clear();
+        snapshot
+            .system
+            .labels
+            .insert("host.name".to_string(), "web-1.example.com".to_string());
+        let resource = resource(&snapshot);
+        let attributes = &resource.attributes;
+
+        assert_eq!(
+            attribute(attributes, "host.name"),
+            Some("web-1.example.com")
+        );
+        assert_eq!(
+            attributes.iter().filter(|a| a.key == "host.name").count(),
+            1
+        );
+        assert_eq!(attribute(attributes, "host.id"), None);
+        assert!(attributes
+            .iter()
+            .all(|a| attribute(attributes, &a.key) != Some("")));
+    }
+
+    #[test]
+    fn maps_architectures() {
+        assert_eq!(host_arch("x86_64"), "amd64");
+        assert_eq!(host_arch("aarch64"), "arm64");
+        assert_eq!(host_arch("i686"), "x86");
+        assert_eq!(host_arch("armv7l"), "arm32");
+        assert_eq!(host_arch("riscv64"), "riscv64");
+    }
+}
@@ -0,0 +1,188 @@
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+
+/// How command output is written to stdout.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Columns aligned for reading in a terminal.
+    Table,
+    Json,
+    /// One JSON object per line.
+    Jsonl,
+    Csv,
+    /// Apache Parquet; only `export` writes it.
+    Parquet,
+}
+
+impl Format {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Table => "table",
+            Format::Json => "json",
+            Format::Jsonl => "jsonl",
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+/// Rows under named columns, buffered so a table can size its columns.
+///
+/// Cells are JSON values so numbers stay numbers in JSON output; `null` is
+/// written as an empty cell in the other formats.
+pub struct Table {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    pub fn new<S: Into<String>>(columns: impl IntoIterator<Item = S>) -> Self {
+        Table {
+            columns: columns.into_iter().map(Into::into).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Value>) {
+        self.rows.push(row);
+    }
+
+    pub fn write(&self, format: Format, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            Format::Table => self.write_aligned(out),
+            Format::Json => {
+                let rows: Vec<Map<String, Value>> = self.objects().collect();
+                serde_json::to_writer_pretty(&mut *out, &rows)?;
+                writeln!(out)
+            }
+            Format::Jsonl => {
+                for row in self.objects() {
+                    serde_json::to_writer(&mut *out, &row)?;
+                    writeln!(out)?;
+                }
+                Ok(())
+            }
+            Format::Csv => {
+                write_csv_row(out, self.columns.iter().map(String::as_str))?;
+                for row in &self.rows {
+                    let cells: Vec<String> = row.iter().map(cell).collect();
+                    write_csv_row(out, cells.iter().map(String::as_str))?;
+                }
+                Ok(())
+            }
+            Format::Parquet => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "parquet is only written by `tcl export`",
+            )),
+        }
+    }
+
+    fn objects(&self) -> impl Iterator<Item = Map<String, Value>> + '_ {
+        self.rows.iter().map(|row| {
+            self.columns
+                .iter()
+                .cloned()
+                .zip(row.iter().cloned())
+                .collect()
+        })
+    }
+
+    /// Pads every column to its widest cell. Numeric columns are right-aligned.
+    fn write_aligned(&self, out: &mut impl Write) -> io::Result<()> {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(cell).collect())
+            .collect();
+        let widths: Vec<usize> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([name.chars().count()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let numeric: Vec<bool> = (0..self.columns.len())
+            .map(|i| {
+                !self.rows.is_empty()
+                    && self
+                        .rows
+                        .iter()
+                        .all(|row| row[i].is_number() || row[i].is_null())
+            })
+            .collect();
+
+        let line = |out: &mut dyn Write, row: &[String]| -> io::Result<()> {
+            let padded: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| {
+                    if numeric[i] {
+                        format!("{:>width$}", cell, width = widths[i])
+                    } else {
+                        format!("{:<width$}", cell, width = widths[i])
+                    }
+                })
+                .collect();
+            writeln!(out, "{}", padded.join("  ").trim_end())
+        };
+
+        line(out, &self.columns)?;
+        for row in &cells {
+            line(out, row)?;
+        }
+        Ok(())
+    }
+}
+
+/// A cell as text: strings unquoted, `null` empty, anything else as JSON.
+pub fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// A database value as JSON. Blobs become lowercase hex strings.
+pub fn json_value(value: libsql::Value) -> Value {
+    match value {
+        libsql::Value::Null => Value::Null,
+        libsql::Value::Integer(n) => Value::from(n),
+        libsql::Value::Real(f) => Value::from(f),
+        libsql::Value::Text(s) => Value::from(s),
+        libsql::Value::Blob(bytes) => Value::from(
+            bytes
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<String>(),
+        ),
+    }
+}
+
+/// Writes one CSV record, quoting fields that contain separators, quotes or
+/// line breaks.
+pub fn write_csv_row<'a>(
+    out: &mut impl Write,
+    fields: impl IntoIterator<Item = &'a str>,
+) -> io::Result<()> {
+    let mut first = true;
+    for field in fields {
+        if !first {
+            out.write_all(b",")?;
+        }
+        first = false;
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(out, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+    }
+    out.write_all(b"\n")
+}
@@ -0,0 +1,167 @@
+use crate::config::{parse_each, Config, ConfigError};
+use serde::{Deserialize, Serialize};
+use sysinfo::{Process, ProcessRefreshKind, System, UpdateKind, Users};
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    Cpu,
+    Memory,
+}
+
+impl SortKey {
+    pub fn parse(key: &str) -> Result<Self, String> {
+        match key.trim() {
+            "cpu" => Ok(SortKey::Cpu),
+            "memory" => Ok(SortKey::Memory),
+            other => Err(format!(
+                "unknown process sort key `{}` (expected cpu or memory)",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Cpu => "cpu",
+            SortKey::Memory => "memory",
+        }
+    }
+}
+
+pub struct ProcessOptions {
+    pub top: usize,
+    pub sort_keys: Vec<SortKey>,
+    /// Record only the executable, dropping arguments that may carry secrets.
+    pub redact_cmdline: bool,
+}
+
+impl ProcessOptions {
+    /// Builds the options from `[processes]`, or returns `None` when the
+    /// process collector is disabled.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Vec<ConfigError>> {
+        if !config.collectors.processes {
+            return Ok(None);
+        }
+
+        let processes = &config.processes;
+        let mut errors = Vec::new();
+        if processes.top.value == 0 {
+            errors.push(
+                processes
+                    .top
+                    .error("process top count must be greater than zero"),
+            );
+        }
+        let sort_keys = parse_each(&processes.sort, SortKey::parse)
+            .map_err(|e| errors.extend(e))
+            .ok();
+
+        match sort_keys {
+            Some(sort_keys) if errors.is_empty() => Ok(Some(ProcessOptions {
+                top: processes.top.value,
+                sort_keys,
+                redact_cmdline: processes.redact_cmdline,
+            })),
+            _ => Err(errors),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProcessInfo {
+    /// The ranking this row belongs to; a process in both top lists appears twice.
+    pub sort_key: SortKey,
+    /// 1-based position within that ranking.
+    pub rank: usize,
+    pub pid: u32,
+    pub name: String,
+    pub cmd: String,
+    pub user: Option<String>,
+    pub memory_bytes: u64,
+    pub cpu_percent: f32,
+    pub disk_read_bytes: u64,
+    pub disk_written_bytes: u64,
+    /// Seconds since the Unix epoch.
+    pub start_time: u64,
+}
+
+pub struct ProcessCollector {
+    options: ProcessOptions,
+    users: Users,
+}
+
+impl ProcessCollector {
+    pub fn new(options: ProcessOptions) -> Self {
+        ProcessCollector {
+            options,
+            users: Users::new(),
+        }
+    }
+
+    /// Refreshes the per-process counters. Call on both sides of the CPU sample
+    /// window so process CPU usage covers the same span as the global figure.
+    pub fn refresh(sys: &mut System) {
+        sys.refresh_processes_specifics(
+            ProcessRefreshKind::new()
+                .with_cpu()
+                .with_memory()
+                .with_disk_usage()
+                .with_cmd(UpdateKind::OnlyIfNotSet)
+                .with_user(UpdateKind::OnlyIfNotSet),
+        );
+    }
+
+    pub fn collect(&mut self, sys: &System) -> Vec<ProcessInfo> {
+        self.users.refresh_list();
+
+        let mut processes = Vec::new();
+        for &sort_key in &self.options.sort_keys {
+            // Linux reports threads as tasks of their process; rank processes only.
+            let mut ranked: Vec<&Process> = sys
+                .processes()
+                .values()
+                .filter(|p| p.thread_kind().is_none())
+                .collect();
+            match sort_key {
+                SortKey::Cpu => ranked.sort_by(|a, b| b.cpu_usage().total_cmp(&a.cpu_usage())),
+                SortKey::Memory => ranked.sort_by_key(|p| std::cmp::Reverse(p.memory())),
+            }
+
+            processes.extend(
+                ranked
+                    .into_iter()
+                    .take(self.options.top)
+                    .enumerate()
+                    .map(|(i, process)| self.process_info(sort_key, i + 1, process)),
+            );
+        }
+        processes
+    }
+
+    fn process_info(&self, sort_key: SortKey, rank: usize, process: &Process) -> ProcessInfo {
+        let cmd = if self.options.redact_cmdline {
+            process.cmd().first().cloned().unwrap_or_default()
+        } else {
+            process.cmd().join(" ")
+        };
+        let disk_usage = process.disk_usage();
+
+        ProcessInfo {
+            sort_key,
+            rank,
+            pid: process.pid().as_u32(),
+            name: process.name().to_string(),
+            cmd,
+            user: process
+                .user_id()
+                .and_then(|uid| self.users.get_user_by_id(uid))
+                .map(|user| user.name().to_string()),
+            memory_bytes: process.memory(),
+            cpu_percent: process.cpu_usage(),
+            disk_read_bytes: disk_usage.total_read_bytes,
+            disk_written_bytes: disk_usage.total_written_bytes,
+            start_time: process.start_time(),
+        }
+    }
+}
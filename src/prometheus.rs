@@ -0,0 +1,439 @@
+use crate::disk::DiskInfo;
+use crate::network::NetworkInfo;
+use crate::process::ProcessInfo;
+use crate::Snapshot;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+/// Label names and values, after the `host` label every sample has.
+type Labels<'a> = Vec<(&'static str, &'a str)>;
+
+const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Serves the latest snapshot at `/metrics` in the Prometheus text format.
+pub struct Exporter {
+    latest: watch::Sender<String>,
+}
+
+impl Exporter {
+    /// Binds `addr` and serves from a background task. Until the first
+    /// [`Exporter::publish`], scrapes get an empty page.
+    pub fn serve(addr: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("could not listen for metrics on {}: {}", addr, e),
+            )
+        })?;
+        listener.set_nonblocking(true)?;
+        let server = Server::from_tcp(listener)
+            .map_err(io::Error::other)?
+            .http1_only(true);
+
+        let (latest, rx) = watch::channel(String::new());
+        let make_service = make_service_fn(move |_| {
+            let rx = rx.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let page = rx.borrow().clone();
+                    async move { Ok::<_, Infallible>(respond(&req, page)) }
+                }))
+            }
+        });
+        tokio::spawn(async move {
+            if let Err(e) = server.serve(make_service).await {
+                error!(error = %e, "metrics server stopped");
+            }
+        });
+        info!(%addr, "serving metrics");
+        Ok(Exporter { latest })
+    }
+
+    /// Replaces what scrapes return with `snapshot`.
+    pub fn publish(&self, snapshot: &Snapshot) {
+        self.latest.send_replace(render(snapshot));
+    }
+}
+
+fn respond(req: &Request<Body>, page: String) -> Response<Body> {
+    let status = match (req.method(), req.uri().path()) {
+        (&Method::GET | &Method::HEAD, "/metrics") => {
+            return Response::builder()
+                .header(CONTENT_TYPE, CONTENT_TYPE_TEXT)
+                .body(Body::from(page))
+                .expect("static response parts are valid");
+        }
+        (_, "/metrics") => StatusCode::METHOD_NOT_ALLOWED,
+        _ => StatusCode::NOT_FOUND,
+    };
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Writes one family at a time, so each metric's samples follow its `# HELP`
+/// and `# TYPE` lines. Every sample is labelled with the host name.
+struct Exposition<'a> {
+    out: String,
+    host: &'a str,
+}
+
+impl Exposition<'_> {
+    fn family<'s>(
+        &mut self,
+        name: &str,
+        kind: &str,
+        help: &str,
+        samples: impl IntoIterator<Item = (Labels<'s>, f64)>,
+    ) {
+        let mut samples = samples.into_iter().peekable();
+        if samples.peek().is_none() {
+            return;
+        }
+        // Writing to a String cannot fail.
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = write!(self.out, "{}{{host=\"{}\"", name, escape(self.host));
+            for (label, value) in labels {
+                let _ = write!(self.out, ",{}=\"{}\"", label, escape(value));
+            }
+            let _ = writeln!(self.out, "}} {}", number(value));
+        }
+    }
+
+    fn gauge<'s>(
+        &mut self,
+        name: &str,
+        help: &str,
+        samples: impl IntoIterator<Item = (Labels<'s>, f64)>,
+    ) {
+        self.family(name, "gauge", help, samples);
+    }
+
+    fn counter<'s>(
+        &mut self,
+        name: &str,
+        help: &str,
+        samples: impl IntoIterator<Item = (Labels<'s>, f64)>,
+    ) {
+        self.family(name, "counter", help, samples);
+    }
+}
+
+/// The snapshot in the Prometheus text exposition format. Collectors that are
+/// disabled, and values that were not measured, are left out.
+pub fn render(snapshot: &Snapshot) -> String {
+    let mut e = Exposition {
+        out: String::new(),
+        host: &snapshot.system.system_host_name,
+    };
+
+    e.gauge(
+        "tcl_collected_at_seconds",
+        "When the snapshot was collected, in seconds since the Unix epoch.",
+        [(Vec::new(), snapshot.collected_at as f64 / 1000.0)],
+    );
+    e.gauge(
+        "tcl_uptime_seconds",
+        "Seconds since the host booted.",
+        [(Vec::new(), snapshot.system.uptime as f64)],
+    );
+    e.gauge(
+        "tcl_host_info",
+        "Host identity; always 1.",
+        [(
+            vec![
+                ("machine_id", snapshot.system.machine_id.as_str()),
+                ("os_version", snapshot.system.long_os_version.as_str()),
+                ("kernel_version", snapshot.system.kernel_version.as_str()),
+                ("cpu_arch", snapshot.system.cpu_arch.as_str()),
+            ],
+            1.0,
+        )],
+    );
+
+    e.gauge(
+        "tcl_disk_total_bytes",
+        "Size of the filesystem.",
+        snapshot
+            .disks
+            .iter()
+            .map(|d| (disk(d), d.total_bytes as f64)),
+    );
+    e.gauge(
+        "tcl_disk_available_bytes",
+        "Space available to unprivileged users.",
+        snapshot
+            .disks
+            .iter()
+            .map(|d| (disk(d), d.available_bytes as f64)),
+    );
+    e.gauge(
+        "tcl_disk_used_bytes",
+        "Space in use.",
+        snapshot
+            .disks
+            .iter()
+            .map(|d| (disk(d), d.used_bytes as f64)),
+    );
+
+    if let Some(cpu) = &snapshot.cpu {
+        e.gauge(
+            "tcl_cpu_usage_percent",
+            "CPU usage across all cores.",
+            [(Vec::new(), f64::from(cpu.usage_percent))],
+        );
+        e.gauge(
+            "tcl_cpu_frequency_mhz",
+            "CPU frequency.",
+            [(Vec::new(), cpu.frequency_mhz as f64)],
+        );
+        e.gauge(
+            "tcl_load1",
+            "One-minute load average.",
+            [(Vec::new(), cpu.load_one)],
+        );
+        e.gauge(
+            "tcl_load5",
+            "Five-minute load average.",
+            [(Vec::new(), cpu.load_five)],
+        );
+        e.gauge(
+            "tcl_load15",
+            "Fifteen-minute load average.",
+            [(Vec::new(), cpu.load_fifteen)],
+        );
+        e.gauge(
+            "tcl_cpu_core_usage_percent",
+            "Usage of one logical core.",
+            cpu.cores
+                .iter()
+                .map(|c| (vec![("core", c.name.as_str())], f64::from(c.usage_percent))),
+        );
+    }
+
+    if let Some(memory) = &snapshot.memory {
+        for (name, help, value) in [
+            (
+                "tcl_memory_total_bytes",
+                "Installed memory.",
+                memory.total_bytes,
+            ),
+            ("tcl_memory_used_bytes", "Memory in use.", memory.used_bytes),
+            (
+                "tcl_memory_available_bytes",
+                "Memory available without swapping.",
+                memory.available_bytes,
+            ),
+            (
+                "tcl_swap_total_bytes",
+                "Swap space.",
+                memory.swap_total_bytes,
+            ),
+            (
+                "tcl_swap_used_bytes",
+                "Swap in use.",
+                memory.swap_used_bytes,
+            ),
+        ] {
+            e.gauge(name, help, [(Vec::new(), value as f64)]);
+        }
+    }
+
+    let networks = &snapshot.networks;
+    e.counter(
+        "tcl_network_received_bytes_total",
+        "Bytes received since the interface came up.",
+        networks
+            .iter()
+            .map(|n| (interface(n), n.received_bytes as f64)),
+    );
+    e.counter(
+        "tcl_network_transmitted_bytes_total",
+        "Bytes sent since the interface came up.",
+        networks
+            .iter()
+            .map(|n| (interface(n), n.transmitted_bytes as f64)),
+    );
+    e.counter(
+        "tcl_network_received_packets_total",
+        "Packets received since the interface came up.",
+        networks
+            .iter()
+            .map(|n| (interface(n), n.packets_received as f64)),
+    );
+    e.counter(
+        "tcl_network_transmitted_packets_total",
+        "Packets sent since the interface came up.",
+        networks
+            .iter()
+            .map(|n| (interface(n), n.packets_transmitted as f64)),
+    );
+    e.counter(
+        "tcl_network_receive_errors_total",
+        "Receive errors since the interface came up.",
+        networks
+            .iter()
+            .map(|n| (interface(n), n.errors_received as f64)),
+    );
+    e.counter(
+        "tcl_network_transmit_errors_total",
+        "Transmit errors since the interface came up.",
+        networks
+            .iter()
+            .map(|n| (interface(n), n.errors_transmitted as f64)),
+    );
+
+    e.gauge(
+        "tcl_sensor_temperature_celsius",
+        "Sensor temperature.",
+        snapshot.sensors.iter().filter_map(|s| {
+            Some((
+                vec![("sensor", s.label.as_str())],
+                f64::from(s.temperature?),
+            ))
+        }),
+    );
+
+    let processes: Vec<_> = snapshot
+        .unique_processes()
+        .map(|p| (p.pid.to_string(), p))
+        .collect();
+    e.gauge(
+        "tcl_process_cpu_percent",
+        "CPU usage of a top process, where 100 is one full core.",
+        processes
+            .iter()
+            .map(|(pid, p)| (process(pid, p), f64::from(p.cpu_percent))),
+    );
+    e.gauge(
+        "tcl_process_memory_bytes",
+        "Resident memory of a top process.",
+        processes
+            .iter()
+            .map(|(pid, p)| (process(pid, p), p.memory_bytes as f64)),
+    );
+
+    e.out
+}
+
+fn disk(d: &DiskInfo) -> Labels<'_> {
+    vec![
+        ("mount_point", d.mount_point.as_str()),
+        ("device", d.name.as_str()),
+        ("file_system", d.file_system.as_str()),
+        ("kind", d.kind.as_str()),
+    ]
+}
+
+fn interface(n: &NetworkInfo) -> Labels<'_> {
+    vec![("interface", n.name.as_str())]
+}
+
+fn process<'a>(pid: &'a str, p: &'a ProcessInfo) -> Labels<'a> {
+    vec![("pid", pid), ("name", p.name.as_str())]
+}
+
+/// Escapes a label value as the text format requires.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::tests::snapshot;
+
+    #[test]
+    fn groups_samples_under_their_help_and_type() {
+        let page = render(&snapshot());
+        let mut families = Vec::new();
+        let mut lines = page.lines().peekable();
+        while let Some(line) = lines.next() {
+            let name = line
+                .strip_prefix("# HELP ")
+                .and_then(|rest| rest.split(' ').next())
+                .unwrap_or_else(|| panic!("expected # HELP, got {:?}", line));
+            let kind = lines.next().unwrap();
+            assert!(kind.starts_with(&format!("# TYPE {} ", name)), "{}", kind);
+            let mut samples = 0;
+            while lines.peek().is_some_and(|l| !l.starts_with('#')) {
+                let sample = lines.next().unwrap();
+                assert!(
+                    sample.starts_with(&format!("{}{{host=\"web 1\"", name)),
+                    "{}",
+                    sample
+                );
+                samples += 1;
+            }
+            assert!(samples > 0, "{} has no samples", name);
+            assert!(!families.contains(&name), "{} repeated", name);
+            families.push(name);
+        }
+
+        assert!(families.contains(&"tcl_disk_used_bytes"));
+        // The fixture has no CPU, memory, network or sensor readings.
+        for name in [
+            "tcl_cpu_usage_percent",
+            "tcl_load1",
+            "tcl_sensor_temperature_celsius",
+        ] {
+            assert!(!families.contains(&name), "{}", name);
+        }
+        assert!(!families
+            .iter()
+            .any(|f| f.starts_with("tcl_memory") || f.starts_with("tcl_network")));
+        // The process ranks in both top lists but is reported once.
+        assert_eq!(
+            page.lines()
+                .filter(|l| l.starts_with("tcl_process_memory_bytes{"))
+                .count(),
+            1
+        );
+        assert!(page.contains(
+            "tcl_disk_used_bytes{host=\"web 1\",mount_point=\"/\",device=\"/dev/sda1\",file_system=\"ext4\",kind=\"SSD\"} 750\n"
+        ));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let mut snapshot = snapshot();
+        snapshot.system.system_host_name = "say \"hi\"".to_string();
+        snapshot.disks[0].mount_point = "C:\\data\nnew".to_string();
+        let page = render(&snapshot);
+        assert!(page.contains(
+            "tcl_disk_total_bytes{host=\"say \\\"hi\\\"\",mount_point=\"C:\\\\data\\nnew\","
+        ));
+        assert_eq!(escape("plain"), "plain");
+    }
+
+    #[test]
+    fn formats_special_numbers() {
+        assert_eq!(number(f64::NAN), "NaN");
+        assert_eq!(number(f64::INFINITY), "+Inf");
+        assert_eq!(number(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(number(1.5), "1.5");
+        assert_eq!(number(750.0), "750");
+    }
+}
@@ -0,0 +1,347 @@
+use crate::output::{json_value, Table};
+use crate::timestamp::format_millis;
+use glob::Pattern;
+use libsql::Connection;
+use serde_json::Value;
+use std::time::Duration;
+
+/// A recorded value that can be queried by name, e.g. `disk.used_percent`.
+pub struct Metric {
+    pub name: &'static str,
+    /// The table the value is read from, joined to `runs` on `run_id`.
+    table: &'static str,
+    /// SQL over the table's columns, which are qualified as `m`.
+    expr: &'static str,
+    /// The column telling apart several values in one run, such as the mount
+    /// point of each disk.
+    series: Option<&'static str>,
+}
+
+const fn metric(
+    name: &'static str,
+    table: &'static str,
+    expr: &'static str,
+    series: Option<&'static str>,
+) -> Metric {
+    Metric {
+        name,
+        table,
+        expr,
+        series,
+    }
+}
+
+pub const METRICS: &[Metric] = &[
+    metric("cpu.usage_percent", "cpus", "m.usage_percent", None),
+    metric("cpu.frequency_mhz", "cpus", "m.frequency_mhz", None),
+    metric("cpu.load_one", "cpus", "m.load_one", None),
+    metric("cpu.load_five", "cpus", "m.load_five", None),
+    metric("cpu.load_fifteen", "cpus", "m.load_fifteen", None),
+    metric(
+        "cpu_core.usage_percent",
+        "cpu_cores",
+        "m.usage_percent",
+        Some("m.name"),
+    ),
+    metric("memory.used_bytes", "memory", "m.used_bytes", None),
+    metric(
+        "memory.available_bytes",
+        "memory",
+        "m.available_bytes",
+        None,
+    ),
+    metric(
+        "memory.used_percent",
+        "memory",
+        "100.0 * m.used_bytes / NULLIF(m.total_bytes, 0)",
+        None,
+    ),
+    metric(
+        "memory.swap_used_bytes",
+        "memory",
+        "m.swap_used_bytes",
+        None,
+    ),
+    metric(
+        "disk.used_bytes",
+        "disks",
+        "m.used_bytes",
+        Some("m.mount_point"),
+    ),
+    metric(
+        "disk.available_bytes",
+        "disks",
+        "m.available_bytes",
+        Some("m.mount_point"),
+    ),
+    metric(
+        "disk.used_percent",
+        "disks",
+        "100.0 * m.used_bytes / NULLIF(m.total_bytes, 0)",
+        Some("m.mount_point"),
+    ),
+    metric(
+        "network.receive_rate",
+        "network_interfaces",
+        "m.receive_rate",
+        Some("m.name"),
+    ),
+    metric(
+        "network.transmit_rate",
+        "network_interfaces",
+        "m.transmit_rate",
+        Some("m.name"),
+    ),
+    metric(
+        "network.received_bytes",
+        "network_interfaces",
+        "m.received_bytes",
+        Some("m.name"),
+    ),
+    metric(
+        "network.transmitted_bytes",
+        "network_interfaces",
+        "m.transmitted_bytes",
+        Some("m.name"),
+    ),
+    metric(
+        "sensor.temperature",
+        "sensors",
+        "m.temperature",
+        Some("m.label"),
+    ),
+];
+
+/// Resolves metric names, which may be globs such as `disk.*`, in the order
+/// of [`METRICS`].
+pub fn select_metrics(patterns: &[String]) -> Result<Vec<&'static Metric>, String> {
+    let patterns = patterns
+        .iter()
+        .map(|p| Pattern::new(p).map_err(|e| format!("invalid metric pattern `{}`: {}", p, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    for pattern in &patterns {
+        if !METRICS.iter().any(|m| pattern.matches(m.name)) {
+            return Err(format!(
+                "no metric matches `{}`; see `tcl query --list-metrics`",
+                pattern.as_str()
+            ));
+        }
+    }
+    Ok(METRICS
+        .iter()
+        .filter(|m| patterns.iter().any(|p| p.matches(m.name)))
+        .collect())
+}
+
+/// Which rows a query reads.
+#[derive(Default)]
+pub struct Filter {
+    /// Host names or machine ids; empty for every host.
+    pub hosts: Vec<String>,
+    /// Inclusive lower bound in UTC milliseconds.
+    pub since: Option<i64>,
+    /// Exclusive upper bound in UTC milliseconds.
+    pub until: Option<i64>,
+}
+
+impl Filter {
+    /// Builds a `WHERE` clause over `runs r` and `hosts h`, appending its
+    /// parameters to `params`.
+    fn sql(&self, params: &mut Vec<libsql::Value>) -> String {
+        let clauses = self.clauses(params);
+        if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        }
+    }
+
+    /// The conditions of [`Filter::sql`], for callers that add their own.
+    pub fn clauses(&self, params: &mut Vec<libsql::Value>) -> Vec<String> {
+        let mut clauses = Vec::new();
+        if let Some(since) = self.since {
+            params.push(since.into());
+            clauses.push(format!("r.collected_at >= ?{}", params.len()));
+        }
+        if let Some(until) = self.until {
+            params.push(until.into());
+            clauses.push(format!("r.collected_at < ?{}", params.len()));
+        }
+        if !self.hosts.is_empty() {
+            let placeholders: Vec<String> = self
+                .hosts
+                .iter()
+                .map(|host| {
+                    params.push(host.clone().into());
+                    format!("?{}", params.len())
+                })
+                .collect();
+            let list = placeholders.join(", ");
+            clauses.push(format!(
+                "(h.host_name IN ({list}) OR h.machine_id IN ({list}))"
+            ));
+        }
+        clauses
+    }
+}
+
+/// What is computed for each host and series.
+pub enum Mode {
+    /// The most recent value.
+    Latest,
+    /// Sample count, minimum, maximum and average over the whole window.
+    Stats,
+    /// Sample count, minimum, maximum and average per time bucket.
+    Series(Duration),
+}
+
+/// Reads `metrics` for the hosts and window in `filter`, one row per metric,
+/// host and series (and bucket, for [`Mode::Series`]).
+pub async fn metrics(
+    conn: &Connection,
+    metrics: &[&Metric],
+    filter: &Filter,
+    mode: &Mode,
+) -> libsql::Result<Table> {
+    let mut table = match mode {
+        Mode::Latest => Table::new(["metric", "host", "series", "time", "value"]),
+        Mode::Stats => Table::new([
+            "metric", "host", "series", "samples", "min", "max", "avg", "first", "last",
+        ]),
+        Mode::Series(_) => Table::new([
+            "metric", "host", "series", "bucket", "samples", "min", "max", "avg",
+        ]),
+    };
+
+    for metric in metrics {
+        let mut params = Vec::new();
+        let filter = filter.sql(&mut params);
+        let series = metric.series.unwrap_or("NULL");
+        let group_series = metric.series.map_or(String::new(), |s| format!(", {}", s));
+        let from = format!(
+            "FROM {table} m
+                JOIN runs r ON r.id = m.run_id
+                LEFT JOIN hosts h ON h.id = r.host_id
+            {filter}",
+            table = metric.table,
+        );
+        let expr = metric.expr;
+
+        let sql = match mode {
+            // SQLite takes the bare columns of a MAX() aggregate from the row
+            // holding the maximum, which is the latest sample.
+            Mode::Latest => format!(
+                "SELECT h.host_name, {series}, MAX(r.collected_at), {expr}
+                {from}
+                GROUP BY r.host_id{group_series}
+                ORDER BY h.host_name, 2"
+            ),
+            Mode::Stats => format!(
+                "SELECT h.host_name, {series}, COUNT({expr}), MIN({expr}), MAX({expr}), AVG({expr}),
+                    MIN(r.collected_at), MAX(r.collected_at)
+                {from}
+                GROUP BY r.host_id{group_series}
+                ORDER BY h.host_name, 2"
+            ),
+            Mode::Series(bucket) => {
+                params.push((bucket.as_millis() as i64).into());
+                let bucket = format!("(r.collected_at / ?{n}) * ?{n}", n = params.len());
+                format!(
+                    "SELECT h.host_name, {series}, {bucket}, COUNT({expr}), MIN({expr}), MAX({expr}), AVG({expr})
+                    {from}
+                    GROUP BY r.host_id{group_series}, 3
+                    ORDER BY h.host_name, 2, 3"
+                )
+            }
+        };
+
+        let mut rows = conn.query(&sql, params).await?;
+        while let Some(row) = rows.next().await? {
+            let mut cells = vec![Value::from(metric.name)];
+            cells.push(json_value(row.get_value(0)?));
+            cells.push(json_value(row.get_value(1)?));
+            match mode {
+                Mode::Latest => {
+                    cells.push(time(row.get_value(2)?));
+                    cells.push(json_value(row.get_value(3)?));
+                }
+                Mode::Stats => {
+                    cells.extend(
+                        (2..6)
+                            .map(|i| row.get_value(i).map(json_value))
+                            .collect::<libsql::Result<Vec<_>>>()?,
+                    );
+                    cells.push(time(row.get_value(6)?));
+                    cells.push(time(row.get_value(7)?));
+                }
+                Mode::Series(_) => {
+                    cells.push(time(row.get_value(2)?));
+                    cells.extend(
+                        (3..7)
+                            .map(|i| row.get_value(i).map(json_value))
+                            .collect::<libsql::Result<Vec<_>>>()?,
+                    );
+                }
+            }
+            table.push(cells);
+        }
+    }
+    Ok(table)
+}
+
+/// A `collected_at` value as an RFC 3339 UTC timestamp.
+fn time(value: libsql::Value) -> Value {
+    match value {
+        libsql::Value::Integer(millis) => Value::from(format_millis(millis)),
+        other => json_value(other),
+    }
+}
+
+/// The hosts seen within `filter`, most recently seen first, with how many
+/// runs each recorded in that window.
+pub async fn hosts(conn: &Connection, filter: &Filter) -> libsql::Result<Table> {
+    let mut params = Vec::new();
+    let filter = filter.sql(&mut params);
+    let mut rows = conn
+        .query(
+            &format!(
+                "SELECT h.host_name, h.machine_id, h.labels, MIN(r.collected_at), MAX(r.collected_at), COUNT(*)
+                FROM runs r JOIN hosts h ON h.id = r.host_id
+                {filter}
+                GROUP BY h.id
+                ORDER BY MAX(r.collected_at) DESC"
+            ),
+            params,
+        )
+        .await?;
+
+    let mut table = Table::new([
+        "host",
+        "machine_id",
+        "labels",
+        "first_seen",
+        "last_seen",
+        "runs",
+    ]);
+    while let Some(row) = rows.next().await? {
+        table.push(vec![
+            json_value(row.get_value(0)?),
+            json_value(row.get_value(1)?),
+            json_value(row.get_value(2)?),
+            time(row.get_value(3)?),
+            time(row.get_value(4)?),
+            json_value(row.get_value(5)?),
+        ]);
+    }
+    Ok(table)
+}
+
+/// Every metric [`metrics`] can read.
+pub fn metric_list() -> Table {
+    let mut table = Table::new(["metric", "series"]);
+    for metric in METRICS {
+        let series = metric.series.map(|s| s.trim_start_matches("m."));
+        table.push(vec![Value::from(metric.name), Value::from(series)]);
+    }
+    table
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use sysinfo::Components;
+
+#[derive(Serialize, Deserialize)]
+pub struct SensorInfo {
+    pub label: String,
+    /// Degrees Celsius. `None` when the sensor reported no usable reading.
+    pub temperature: Option<f32>,
+    /// The highest temperature observed since the agent started, not the
+    /// sensor's rated maximum.
+    pub max: Option<f32>,
+    pub critical: Option<f32>,
+}
+
+/// How often the sensor list is checked for sensors that appeared or went away.
+const RELIST_EVERY: Duration = Duration::from_secs(600);
+
+/// Keeps the sensor list and each sensor's peak temperature between cycles.
+pub struct SensorCollector {
+    components: Components,
+    /// When the list was last checked; `None` before the first collection.
+    listed_at: Option<Instant>,
+    /// Highest reading per sensor, keyed by label and the sensor's position
+    /// among those sharing that label. Kept here rather than taken from
+    /// sysinfo, whose maximum only spans the last two refreshes.
+    peaks: HashMap<(String, usize), f32>,
+}
+
+impl SensorCollector {
+    pub fn new() -> Self {
+        SensorCollector {
+            components: Components::new(),
+            listed_at: None,
+            peaks: HashMap::new(),
+        }
+    }
+
+    /// Refreshes the known sensors in place. Every [`RELIST_EVERY`] the list is
+    /// rebuilt, and replaces the current one only if the labels changed.
+    pub fn collect(&mut self) -> Vec<SensorInfo> {
+        match self.listed_at {
+            None => {
+                self.components.refresh_list();
+                self.listed_at = Some(Instant::now());
+            }
+            Some(at) if at.elapsed() >= RELIST_EVERY => {
+                let listed = Components::new_with_refreshed_list();
+                if labels(&listed) != labels(&self.components) {
+                    self.components = listed;
+                } else {
+                    self.components.refresh();
+                }
+                self.listed_at = Some(Instant::now());
+            }
+            Some(_) => self.components.refresh(),
+        }
+
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        self.components
+            .iter()
+            .map(|component| {
+                let label = component.label();
+                let n = seen.entry(label).or_default();
+                let key = (label.to_string(), *n);
+                *n += 1;
+
+                let temperature = finite(component.temperature());
+                let reading = match (temperature, finite(component.max())) {
+                    (Some(t), Some(m)) => Some(t.max(m)),
+                    (t, m) => t.or(m),
+                };
+                let max = match (reading, self.peaks.get(&key)) {
+                    (Some(r), Some(&peak)) => Some(r.max(peak)),
+                    (r, peak) => r.or(peak.copied()),
+                };
+                if let Some(max) = max {
+                    self.peaks.insert(key, max);
+                }
+                SensorInfo {
+                    label: label.to_string(),
+                    temperature,
+                    max,
+                    critical: component.critical().and_then(finite),
+                }
+            })
+            .collect()
+    }
+}
+
+fn labels(components: &Components) -> Vec<&str> {
+    components.iter().map(|c| c.label()).collect()
+}
+
+fn finite(value: f32) -> Option<f32> {
+    value.is_finite().then_some(value)
+}
@@ -0,0 +1,900 @@
+use crate::config::{gather, Config, ConfigError, Origin, SinkConfig, Value};
+use crate::db::init_db;
+use crate::encoding::Encoding;
+use crate::error::{Error, Result};
+use crate::otlp::{self, Protocol};
+use crate::Snapshot;
+use async_trait::async_trait;
+use futures::future::join_all;
+use hyper::client::HttpConnector;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Client, Method, Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceResponse;
+use prost::Message;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tonic::metadata::MetadataMap;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tracing::{debug, error, info, warn};
+
+/// A destination for collected snapshots.
+#[async_trait]
+pub trait Sink: Send {
+    /// Identifies the sink in logs and errors, e.g. `file /var/log/tcl.jsonl`.
+    fn name(&self) -> &str;
+
+    /// Delivers one snapshot. A sink that can retry later, such as libsql with
+    /// its spool, keeps the snapshot before returning the error.
+    async fn write(&mut self, snapshot: &Snapshot) -> Result<()>;
+
+    /// How often [`Sink::sync`] should run in daemon mode; `None` for never.
+    fn sync_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Periodic upkeep, such as pulling changes into an embedded replica. Also
+    /// run once before the agent exits. The sink logs its own failures.
+    async fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A validated `[[sinks]]` entry.
+pub enum SinkTarget {
+    /// The database in `[database]`; see [`crate::db::DbTarget`].
+    Libsql,
+    /// Each snapshot on standard output.
+    Stdout { encoding: Encoding },
+    /// Each snapshot appended to `path`. Once the file would grow past
+    /// `max_bytes` it is renamed to `path.1`, older files shift up, and only
+    /// `keep` of them are kept.
+    File {
+        path: PathBuf,
+        max_bytes: u64,
+        keep: usize,
+        encoding: Encoding,
+    },
+    /// Each snapshot POSTed to `url`, e.g. an InfluxDB `/api/v2/write`
+    /// endpoint with the `influx` format.
+    Http {
+        url: Uri,
+        headers: HeaderMap,
+        timeout: Duration,
+        encoding: Encoding,
+    },
+    /// Each snapshot written to a TCP connection to `address`, such as a
+    /// Graphite (carbon) plaintext port.
+    Tcp {
+        address: String,
+        timeout: Duration,
+        encoding: Encoding,
+    },
+    /// Each snapshot sent as UDP datagrams to `address`, such as an InfluxDB or
+    /// Telegraf UDP listener. Lines are never split across datagrams.
+    Udp { address: String, encoding: Encoding },
+    /// Each snapshot exported as OpenTelemetry metrics to a collector at
+    /// `url`; see [`crate::otlp`].
+    Otlp {
+        url: Uri,
+        protocol: Protocol,
+        headers: HeaderMap,
+        timeout: Duration,
+    },
+}
+
+impl SinkTarget {
+    /// Checks every setting the sink's type uses, reporting each invalid one.
+    pub fn from_config(sink: &SinkConfig) -> std::result::Result<Self, Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        match Self::check(sink, &mut errors) {
+            Some(target) if errors.is_empty() => Ok(target),
+            _ => Err(errors),
+        }
+    }
+
+    /// The target, or `None` once a problem has been added to `errors`.
+    fn check(sink: &SinkConfig, errors: &mut Vec<ConfigError>) -> Option<Self> {
+        let encoding = gather(errors, Encoding::from_config(&sink.format));
+        // Checked for every type, since it also bounds each write.
+        let timeout = gather(errors, timeout(sink));
+        match sink.kind.value.as_str() {
+            "libsql" => Some(SinkTarget::Libsql),
+            "stdout" => Some(SinkTarget::Stdout {
+                encoding: encoding?,
+            }),
+            "file" => {
+                let path = sink.path.clone();
+                if path.is_none() {
+                    errors.push(sink.kind.error("file sink needs a path"));
+                }
+                Some(SinkTarget::File {
+                    path: path?,
+                    max_bytes: sink.max_bytes,
+                    keep: sink.keep,
+                    encoding: encoding?,
+                })
+            }
+            "http" => {
+                let url = match &sink.url {
+                    Some(url) => gather(errors, url_from_config(url)),
+                    None => {
+                        errors.push(sink.kind.error("http sink needs a url"));
+                        None
+                    }
+                };
+                let headers = headers(sink, errors);
+                Some(SinkTarget::Http {
+                    url: url?,
+                    headers: headers?,
+                    timeout: timeout?,
+                    encoding: encoding?,
+                })
+            }
+            "tcp" => {
+                let address = gather(errors, address(sink));
+                Some(SinkTarget::Tcp {
+                    address: address?,
+                    timeout: timeout?,
+                    encoding: encoding?,
+                })
+            }
+            "udp" => {
+                if encoding == Some(Encoding::Json) {
+                    let at = match sink.format.origin {
+                        Origin::Default => &sink.kind,
+                        _ => &sink.format,
+                    };
+                    errors.push(at.error("udp sink needs format influx or graphite"));
+                }
+                let address = gather(errors, address(sink));
+                Some(SinkTarget::Udp {
+                    address: address?,
+                    encoding: encoding.filter(|e| *e != Encoding::Json)?,
+                })
+            }
+            "otlp" => {
+                let protocol = gather(errors, Protocol::from_config(&sink.protocol));
+                let url = match &sink.url {
+                    Some(url) => gather(errors, url_from_config(url)),
+                    None => protocol.map(|p| Uri::from_static(p.default_url())),
+                };
+                let headers = headers(sink, errors);
+                Some(SinkTarget::Otlp {
+                    url: url?,
+                    protocol: protocol?,
+                    headers: headers?,
+                    timeout: timeout?,
+                })
+            }
+            other => {
+                errors.push(sink.kind.error(format!(
+                    "unknown sink type `{}` (expected libsql, stdout, file, http, tcp, udp or otlp)",
+                    other
+                )));
+                None
+            }
+        }
+    }
+}
+
+fn url_from_config(url: &Value<String>) -> std::result::Result<Uri, ConfigError> {
+    let uri: Uri = url
+        .value
+        .parse()
+        .map_err(|e| url.error(format!("invalid url `{}`: {}", url.value, e)))?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) {
+        return Err(url.error(format!(
+            "url `{}` must start with http:// or https://",
+            url.value
+        )));
+    }
+    Ok(uri)
+}
+
+/// The sink's headers, or `None` after adding an error for each invalid one,
+/// located where that header was set.
+fn headers(sink: &SinkConfig, errors: &mut Vec<ConfigError>) -> Option<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let mut valid = true;
+    for (name, value) in &sink.headers {
+        let Ok(header) = HeaderName::from_bytes(name.as_bytes()) else {
+            errors.push(value.error(format!("invalid header name `{}`", name)));
+            valid = false;
+            continue;
+        };
+        match HeaderValue::from_str(&value.value) {
+            Ok(value) => {
+                headers.insert(header, value);
+            }
+            Err(_) => {
+                errors.push(value.error(format!("invalid value for header {}", name)));
+                valid = false;
+            }
+        }
+    }
+    valid.then_some(headers)
+}
+
+fn timeout(sink: &SinkConfig) -> std::result::Result<Duration, ConfigError> {
+    if sink.timeout.value == 0 {
+        return Err(sink.timeout.error("timeout must be greater than zero"));
+    }
+    Ok(Duration::from_secs(sink.timeout.value))
+}
+
+/// A `host:port` address. The host is resolved when connecting, not here.
+fn address(sink: &SinkConfig) -> std::result::Result<String, ConfigError> {
+    let address = sink.address.as_ref().ok_or_else(|| {
+        sink.kind
+            .error(format!("{} sink needs an address", sink.kind.value))
+    })?;
+    let valid = match address.value.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p > 0),
+        None => false,
+    };
+    if !valid {
+        return Err(address.error(format!(
+            "invalid address `{}` (expected host:port)",
+            address.value
+        )));
+    }
+    Ok(address.value.clone())
+}
+
+fn https_client() -> Client<HttpsConnector<HttpConnector>> {
+    Client::builder().build(
+        HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build(),
+    )
+}
+
+/// Every configured sink, written to side by side.
+pub struct Sinks {
+    /// Each sink with its `timeout`.
+    sinks: Vec<(Box<dyn Sink>, Duration)>,
+}
+
+impl Sinks {
+    /// Opens the sinks in `[[sinks]]`. Like [`init_db`], nothing here touches
+    /// the network, so an unreachable destination does not stop the agent.
+    pub async fn open(config: &Config) -> Result<Self> {
+        let targets = config
+            .sinks
+            .iter()
+            .map(SinkTarget::from_config)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let mut sinks: Vec<(Box<dyn Sink>, Duration)> = Vec::new();
+        for (target, entry) in targets.into_iter().zip(&config.sinks) {
+            let sink: Box<dyn Sink> = match target {
+                SinkTarget::Libsql => Box::new(init_db(config).await?),
+                SinkTarget::Stdout { encoding } => Box::new(StdoutSink { encoding }),
+                SinkTarget::File {
+                    path,
+                    max_bytes,
+                    keep,
+                    encoding,
+                } => Box::new(FileSink {
+                    name: format!("file {}", path.display()),
+                    path,
+                    max_bytes,
+                    keep,
+                    encoding,
+                    file: None,
+                    size: 0,
+                }),
+                SinkTarget::Http {
+                    url,
+                    headers,
+                    timeout,
+                    encoding,
+                } => Box::new(HttpSink {
+                    name: format!("http {}", url),
+                    client: https_client(),
+                    url,
+                    headers,
+                    timeout,
+                    encoding,
+                }),
+                SinkTarget::Tcp {
+                    address,
+                    timeout,
+                    encoding,
+                } => Box::new(TcpSink {
+                    name: format!("tcp {}", address),
+                    address,
+                    timeout,
+                    encoding,
+                    stream: None,
+                }),
+                SinkTarget::Udp { address, encoding } => Box::new(UdpSink {
+                    name: format!("udp {}", address),
+                    address,
+                    encoding,
+                    socket: None,
+                }),
+                SinkTarget::Otlp {
+                    url,
+                    protocol,
+                    headers,
+                    timeout,
+                } => Box::new(OtlpSink {
+                    name: format!("otlp {}", url),
+                    transport: match protocol {
+                        Protocol::Grpc => {
+                            let mut endpoint = Endpoint::from(url.clone())
+                                .connect_timeout(timeout)
+                                .timeout(timeout);
+                            if url.scheme_str() == Some("https") {
+                                endpoint = endpoint
+                                    .tls_config(ClientTlsConfig::new())
+                                    .map_err(|e| Error::Config(format!("otlp {}: {}", url, e)))?;
+                            }
+                            OtlpTransport::Grpc(MetricsServiceClient::new(endpoint.connect_lazy()))
+                        }
+                        Protocol::HttpProtobuf => OtlpTransport::Http {
+                            client: https_client(),
+                            url,
+                        },
+                    },
+                    headers,
+                    timeout,
+                }),
+            };
+            sinks.push((sink, Duration::from_secs(entry.timeout.value)));
+        }
+        Ok(Sinks { sinks })
+    }
+
+    /// Writes `snapshot` to every sink at once, so a slow or broken sink does
+    /// not hold back the others, and gives up on a sink after its timeout so a
+    /// hung one cannot stall collection. Each failure is logged with the sink's
+    /// name, and the first is returned.
+    pub async fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let results = join_all(self.sinks.iter_mut().map(|(sink, timeout)| async move {
+            let name = sink.name().to_string();
+            let result = within(*timeout, &name, sink.write(snapshot)).await;
+            if let Err(e) = &result {
+                error!(sink = sink.name(), exit_code = e.exit_code(), "{}", e);
+            }
+            result
+        }))
+        .await;
+        results
+            .into_iter()
+            .find_map(Result::err)
+            .map_or(Ok(()), Err)
+    }
+
+    /// The shortest sync interval of any sink.
+    pub fn sync_interval(&self) -> Option<Duration> {
+        self.sinks
+            .iter()
+            .filter_map(|(s, _)| s.sync_interval())
+            .min()
+    }
+
+    /// Syncs every sink, each bounded by its timeout, returning the first
+    /// failure.
+    pub async fn sync(&mut self) -> Result<()> {
+        let results = join_all(self.sinks.iter_mut().map(|(sink, timeout)| async move {
+            let name = sink.name().to_string();
+            within(*timeout, &name, sink.sync()).await
+        }))
+        .await;
+        results
+            .into_iter()
+            .find_map(Result::err)
+            .map_or(Ok(()), Err)
+    }
+}
+
+/// Runs a sink's `work`, failing with a delivery error if it takes longer than
+/// `timeout`. The work is dropped part way, which every sink tolerates: libsql
+/// spools before writing, and the others reconnect on the next write.
+async fn within(
+    timeout: Duration,
+    sink: &str,
+    work: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    match tokio::time::timeout(timeout, work).await {
+        Ok(result) => result,
+        Err(_) => Err(delivery_error(
+            sink,
+            format!("gave up after {}s", timeout.as_secs()),
+        )),
+    }
+}
+
+struct StdoutSink {
+    encoding: Encoding,
+}
+
+#[async_trait]
+impl Sink for StdoutSink {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    async fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let line = self.encoding.encode(snapshot).map_err(io::Error::from)?;
+        let mut out = io::stdout().lock();
+        out.write_all(&line)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+struct FileSink {
+    name: String,
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    encoding: Encoding,
+    /// Opened on the first write.
+    file: Option<File>,
+    size: u64,
+}
+
+impl FileSink {
+    fn open(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().expect("opened above"))
+    }
+
+    /// Shifts `path.N` to `path.N+1`, dropping the oldest, and moves the
+    /// current file to `path.1`.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        let numbered = |n: usize| {
+            let mut name = OsString::from(self.path.as_os_str());
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                match fs::rename(numbered(n), numbered(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        debug!(path = %self.path.display(), "rotated sink file");
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let line = self.encoding.encode(snapshot).map_err(io::Error::from)?;
+
+        self.open()?;
+        let len = line.len() as u64;
+        if self.max_bytes > 0 && self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        let file = self.open()?;
+        file.write_all(&line)?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+/// POSTs each snapshot with its id as the `Idempotency-Key`, so a receiver can
+/// drop a snapshot it has already stored. A snapshot that cannot be delivered
+/// is not retried.
+struct HttpSink {
+    name: String,
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: Uri,
+    headers: HeaderMap,
+    timeout: Duration,
+    encoding: Encoding,
+}
+
+fn delivery_error(sink: &str, message: impl Into<String>) -> Error {
+    Error::Delivery {
+        sink: sink.to_string(),
+        message: message.into(),
+    }
+}
+
+impl HttpSink {
+    fn delivery_error(&self, message: impl Into<String>) -> Error {
+        delivery_error(&self.name, message)
+    }
+}
+
+#[async_trait]
+impl Sink for HttpSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let body = self.encoding.encode(snapshot).map_err(io::Error::from)?;
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(CONTENT_TYPE, self.encoding.content_type())
+            .header("idempotency-key", snapshot.id.as_str())
+            .body(Body::from(body))
+            .map_err(|e| self.delivery_error(e.to_string()))?;
+        request.headers_mut().extend(self.headers.clone());
+
+        let response = tokio::time::timeout(self.timeout, self.client.request(request))
+            .await
+            .map_err(|_| {
+                self.delivery_error(format!("no response within {}s", self.timeout.as_secs()))
+            })?
+            .map_err(|e| self.delivery_error(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(self.delivery_error(format!("server answered {}", response.status())));
+        }
+        info!(
+            sink = self.name.as_str(),
+            run = snapshot.id.as_str(),
+            "delivered snapshot"
+        );
+        Ok(())
+    }
+}
+
+/// Keeps one connection open across snapshots and reconnects after a failure.
+/// A snapshot that cannot be delivered is not retried.
+struct TcpSink {
+    name: String,
+    address: String,
+    timeout: Duration,
+    encoding: Encoding,
+    stream: Option<TcpStream>,
+}
+
+impl TcpSink {
+    /// Holds the stream only while sending, so a send that fails or is
+    /// cancelled drops a connection that may have part of a line on it.
+    async fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => TcpStream::connect(&self.address).await?,
+        };
+        stream.write_all(data).await?;
+        stream.flush().await?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for TcpSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let data = self.encoding.encode(snapshot).map_err(io::Error::from)?;
+        let result = match tokio::time::timeout(self.timeout, self.send(&data)).await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(_) => Err(format!("not sent within {}s", self.timeout.as_secs())),
+        };
+        if let Err(message) = result {
+            return Err(delivery_error(&self.name, message));
+        }
+        debug!(
+            sink = self.name.as_str(),
+            run = snapshot.id.as_str(),
+            "delivered snapshot"
+        );
+        Ok(())
+    }
+}
+
+/// Datagrams stay under a typical Ethernet MTU so they are not fragmented.
+const MAX_DATAGRAM: usize = 1400;
+
+/// Sends each snapshot as datagrams of whole lines. A datagram that is lost on
+/// the way goes unnoticed.
+struct UdpSink {
+    name: String,
+    address: String,
+    encoding: Encoding,
+    /// Bound and connected on the first write.
+    socket: Option<UdpSocket>,
+}
+
+impl UdpSink {
+    async fn socket(&mut self) -> io::Result<&UdpSocket> {
+        if self.socket.is_none() {
+            let target = lookup_host(&self.address).await?.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "address did not resolve")
+            })?;
+            let local: SocketAddr = if target.is_ipv4() {
+                "0.0.0.0:0".parse().expect("valid address")
+            } else {
+                "[::]:0".parse().expect("valid address")
+            };
+            let socket = UdpSocket::bind(local).await?;
+            socket.connect(target).await?;
+            self.socket = Some(socket);
+        }
+        Ok(self.socket.as_ref().expect("bound above"))
+    }
+
+    async fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let socket = self.socket().await?;
+        for datagram in datagrams(data) {
+            socket.send(datagram).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Groups newline-terminated `data` into runs of whole lines of at most
+/// [`MAX_DATAGRAM`] bytes; a longer line gets a datagram of its own.
+fn datagrams(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let (mut start, mut end) = (0, 0);
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        if end > start && end - start + line.len() > MAX_DATAGRAM {
+            chunks.push(&data[start..end]);
+            start = end;
+        }
+        end += line.len();
+    }
+    if end > start {
+        chunks.push(&data[start..end]);
+    }
+    chunks
+}
+
+#[async_trait]
+impl Sink for UdpSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let data = self.encoding.encode(snapshot).map_err(io::Error::from)?;
+        if let Err(e) = self.send(&data).await {
+            self.socket = None;
+            return Err(delivery_error(&self.name, e.to_string()));
+        }
+        debug!(
+            sink = self.name.as_str(),
+            run = snapshot.id.as_str(),
+            "sent snapshot"
+        );
+        Ok(())
+    }
+}
+
+enum OtlpTransport {
+    Grpc(MetricsServiceClient<Channel>),
+    Http {
+        client: Client<HttpsConnector<HttpConnector>>,
+        url: Uri,
+    },
+}
+
+/// Exports each snapshot to an OpenTelemetry collector. A snapshot that cannot
+/// be delivered is not retried; the next one carries the same cumulative sums.
+struct OtlpSink {
+    name: String,
+    transport: OtlpTransport,
+    /// Sent as gRPC metadata or HTTP headers.
+    headers: HeaderMap,
+    timeout: Duration,
+}
+
+impl OtlpSink {
+    async fn export(
+        &mut self,
+        snapshot: &Snapshot,
+    ) -> std::result::Result<ExportMetricsServiceResponse, String> {
+        let request = otlp::request(snapshot);
+        match &mut self.transport {
+            OtlpTransport::Grpc(client) => {
+                let mut request = tonic::Request::new(request);
+                *request.metadata_mut() = MetadataMap::from_headers(self.headers.clone());
+                let response = client
+                    .export(request)
+                    .await
+                    .map_err(|status| format!("{:?}: {}", status.code(), status.message()))?;
+                Ok(response.into_inner())
+            }
+            OtlpTransport::Http { client, url } => {
+                let mut request = Request::builder()
+                    .method(Method::POST)
+                    .uri(url.clone())
+                    .header(CONTENT_TYPE, "application/x-protobuf")
+                    .body(Body::from(request.encode_to_vec()))
+                    .map_err(|e| e.to_string())?;
+                request.headers_mut().extend(self.headers.clone());
+
+                let response = client.request(request).await.map_err(|e| e.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("collector answered {}", response.status()));
+                }
+                let body = hyper::body::to_bytes(response.into_body())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                ExportMetricsServiceResponse::decode(body)
+                    .map_err(|e| format!("unreadable response: {}", e))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for OtlpSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&mut self, snapshot: &Snapshot) -> Result<()> {
+        let timeout = self.timeout;
+        let response = match tokio::time::timeout(timeout, self.export(snapshot)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("no response within {}s", timeout.as_secs())),
+        }
+        .map_err(|message| delivery_error(&self.name, message))?;
+
+        // The collector kept the rest of the snapshot.
+        if let Some(partial) = response
+            .partial_success
+            .filter(|p| p.rejected_data_points > 0)
+        {
+            warn!(
+                sink = self.name.as_str(),
+                rejected = partial.rejected_data_points,
+                "collector rejected data points: {}",
+                partial.error_message
+            );
+        }
+        info!(
+            sink = self.name.as_str(),
+            run = snapshot.id.as_str(),
+            "delivered snapshot"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::tests::snapshot;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn line(len: usize) -> Vec<u8> {
+        let mut line = vec![b'x'; len - 1];
+        line.push(b'\n');
+        line
+    }
+
+    #[test]
+    fn packs_whole_lines_into_datagrams() {
+        assert!(datagrams(b"").is_empty());
+
+        let data = [line(700), line(700), line(700)].concat();
+        let chunks = datagrams(&data);
+        assert_eq!(
+            chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+            [1400, 700]
+        );
+
+        let data = [line(10), line(1391)].concat();
+        assert_eq!(datagrams(&data).len(), 2);
+        let data = [line(10), line(1390)].concat();
+        assert_eq!(datagrams(&data).len(), 1);
+    }
+
+    #[test]
+    fn gives_a_long_line_its_own_datagram() {
+        let data = [line(10), line(2000), line(10)].concat();
+        let chunks = datagrams(&data);
+        assert_eq!(
+            chunks.iter().map(|c| c.len()).collect::<Vec<_>>(),
+            [10, 2000, 10]
+        );
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[tokio::test]
+    async fn tcp_sink_writes_encoded_snapshots() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut sink = TcpSink {
+            name: format!("tcp {}", address),
+            address,
+            timeout: Duration::from_secs(5),
+            encoding: Encoding::Graphite,
+            stream: None,
+        };
+
+        let snapshot = snapshot();
+        sink.write(&snapshot).await.unwrap();
+        sink.write(&snapshot).await.unwrap();
+        let (mut conn, _) = listener.accept().await.unwrap();
+        drop(sink);
+
+        let mut received = Vec::new();
+        conn.read_to_end(&mut received).await.unwrap();
+        let expected = Encoding::Graphite.encode(&snapshot).unwrap();
+        // Both snapshots went over the one connection.
+        assert_eq!(received, [expected.clone(), expected].concat());
+    }
+
+    #[tokio::test]
+    async fn tcp_sink_fails_without_a_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let mut sink = TcpSink {
+            name: format!("tcp {}", address),
+            address,
+            timeout: Duration::from_secs(5),
+            encoding: Encoding::Influx,
+            stream: None,
+        };
+        let error = sink.write(&snapshot()).await.unwrap_err();
+        assert!(matches!(error, Error::Delivery { .. }), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn udp_sink_sends_whole_lines() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        let mut sink = UdpSink {
+            name: format!("udp {}", address),
+            address,
+            encoding: Encoding::Influx,
+            socket: None,
+        };
+
+        let snapshot = snapshot();
+        sink.write(&snapshot).await.unwrap();
+
+        let expected = Encoding::Influx.encode(&snapshot).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0; 65536];
+        while received.len() < expected.len() {
+            let n = socket.recv(&mut buf).await.unwrap();
+            assert!(n <= MAX_DATAGRAM && buf[..n].ends_with(b"\n"));
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, expected);
+    }
+}
@@ -0,0 +1,262 @@
+use crate::config::Config;
+use crate::db::{insert_into_db, DbTarget};
+use crate::Snapshot;
+use libsql::Connection;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// On-disk queue of snapshots on their way to the database.
+///
+/// Each snapshot is a JSON file named after its collection time, so a directory
+/// listing sorted by name is oldest-first. When the spool grows past its size
+/// limit the oldest records are evicted.
+///
+/// Each database has a spool of its own, so a snapshot is only ever replayed
+/// into the database it was meant for.
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl Spool {
+    /// The spool for `target`: a subdirectory of `[spool]`'s directory, which
+    /// defaults to `spool` in the state directory, named after a hash of the
+    /// database's URL or path.
+    pub fn new(config: &Config, target: &DbTarget) -> Self {
+        let destination = match target {
+            DbTarget::Local(path) => std::path::absolute(path)
+                .unwrap_or_else(|_| path.clone())
+                .to_string_lossy()
+                .into_owned(),
+            DbTarget::Remote { url, .. } | DbTarget::Replica { url, .. } => url.clone(),
+        };
+        let base = config
+            .spool
+            .dir
+            .clone()
+            .unwrap_or_else(|| config.state_dir.join("spool"));
+        Spool {
+            dir: base.join(format!("{:016x}", fnv1a(destination.as_bytes()))),
+            max_bytes: config.spool.max_bytes,
+        }
+    }
+
+    /// Stores a snapshot, then evicts the oldest records until the spool fits
+    /// within its size limit.
+    pub fn push(&self, snapshot: &Snapshot) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let name = format!("{:013}-{}.json", snapshot.collected_at, snapshot.id);
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp, serde_json::to_vec(snapshot)?)?;
+        fs::rename(&tmp, self.dir.join(name))?;
+
+        let entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, size)| size).sum();
+        for (path, size) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(&path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+
+    /// Inserts spooled snapshots oldest-first, removing each once it is written.
+    /// Stops at the first database error so order is preserved; returns how many
+    /// records were replayed.
+    ///
+    /// Cancelling a replay is safe: a record inserted but not yet removed is
+    /// skipped next time by its idempotency key.
+    ///
+    /// Records that no longer deserialize are renamed to `*.corrupt` and skipped.
+    pub async fn replay(&self, conn: &Connection) -> Result<usize, libsql::Error> {
+        let entries = self.entries().unwrap_or_default();
+
+        let mut replayed = 0;
+        for (path, _) in entries {
+            let snapshot: Snapshot = match fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+            {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "skipping unreadable spooled snapshot");
+                    let _ = fs::rename(&path, path.with_extension("corrupt"));
+                    continue;
+                }
+            };
+
+            let started = Instant::now();
+            insert_into_db(conn, &snapshot).await?;
+            info!(
+                run = snapshot.id.as_str(),
+                elapsed_ms = started.elapsed().as_secs_f64() * 1000.0,
+                "recorded snapshot"
+            );
+            if let Err(e) = fs::remove_file(&path) {
+                // The idempotency key makes a second replay of this record harmless.
+                warn!(path = %path.display(), error = %e, "could not remove replayed snapshot");
+            }
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+
+    /// How many snapshots are waiting to be replayed, and their total size in bytes.
+    pub fn backlog(&self) -> io::Result<(usize, u64)> {
+        let entries = self.entries()?;
+        Ok((entries.len(), entries.iter().map(|(_, size)| size).sum()))
+    }
+
+    /// Spooled records and their sizes, oldest first.
+    fn entries(&self) -> io::Result<Vec<(PathBuf, u64)>> {
+        let mut entries = Vec::new();
+        let dir = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(e),
+        };
+
+        for entry in dir {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                entries.push((path, entry.metadata()?.len()));
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` stays the same across Rust releases,
+/// so upgrading does not orphan a spool.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::tests::snapshot;
+    use crate::migrations;
+    use libsql::{Builder, Database};
+
+    /// An empty spool in a directory of its own under the system temp dir.
+    fn spool(name: &str, max_bytes: u64) -> Spool {
+        let dir = std::env::temp_dir().join(format!("tcl-spool-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Spool { dir, max_bytes }
+    }
+
+    fn run(n: i64) -> Snapshot {
+        let mut snapshot = snapshot();
+        snapshot.id = format!("run-{}", n);
+        snapshot.collected_at = 1_717_243_200_000 + n;
+        snapshot
+    }
+
+    async fn database() -> (Database, Connection) {
+        let database = Builder::new_local(":memory:").build().await.unwrap();
+        let conn = database.connect().unwrap();
+        migrations::migrate(&conn).await.unwrap();
+        (database, conn)
+    }
+
+    async fn stored(conn: &Connection) -> Vec<String> {
+        let mut rows = conn
+            .query("SELECT idempotency_key FROM runs ORDER BY id", ())
+            .await
+            .unwrap();
+        let mut keys = Vec::new();
+        while let Some(row) = rows.next().await.unwrap() {
+            keys.push(row.get(0).unwrap());
+        }
+        keys
+    }
+
+    fn names(spool: &Spool) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&spool.dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn evicts_the_oldest_records_first() {
+        let size = serde_json::to_vec(&run(1)).unwrap().len() as u64;
+        let spool = spool("evict", 2 * size);
+        for n in [2, 1, 3] {
+            spool.push(&run(n)).unwrap();
+        }
+
+        let names = names(&spool);
+        assert_eq!(names.len(), 2);
+        assert!(names[0].ends_with("-run-2.json"), "{:?}", names);
+        assert!(names[1].ends_with("-run-3.json"), "{:?}", names);
+        assert_eq!(spool.backlog().unwrap(), (2, 2 * size));
+        fs::remove_dir_all(&spool.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replays_oldest_first_and_empties_the_spool() {
+        let spool = spool("replay", u64::MAX);
+        for n in [3, 1, 2] {
+            spool.push(&run(n)).unwrap();
+        }
+        let (_database, conn) = database().await;
+
+        assert_eq!(spool.replay(&conn).await.unwrap(), 3);
+        assert_eq!(stored(&conn).await, ["run-1", "run-2", "run-3"]);
+        assert_eq!(spool.backlog().unwrap(), (0, 0));
+        assert_eq!(spool.replay(&conn).await.unwrap(), 0);
+        fs::remove_dir_all(&spool.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn stops_replaying_at_the_first_error() {
+        let spool = spool("stop", u64::MAX);
+        for n in 1..=3 {
+            spool.push(&run(n)).unwrap();
+        }
+        let (_database, conn) = database().await;
+        conn.execute(
+            "CREATE TRIGGER reject_run_2 BEFORE INSERT ON runs
+            WHEN NEW.idempotency_key = 'run-2'
+            BEGIN SELECT RAISE(ABORT, 'rejected'); END",
+            (),
+        )
+        .await
+        .unwrap();
+
+        assert!(spool.replay(&conn).await.is_err());
+        assert_eq!(stored(&conn).await, ["run-1"]);
+        let names = names(&spool);
+        assert_eq!(names.len(), 2);
+        assert!(names[0].ends_with("-run-2.json") && names[1].ends_with("-run-3.json"));
+        fs::remove_dir_all(&spool.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sets_aside_corrupt_records() {
+        let spool = spool("corrupt", u64::MAX);
+        spool.push(&run(1)).unwrap();
+        fs::write(spool.dir.join("0000000000000-garbage.json"), b"{not json").unwrap();
+        let (_database, conn) = database().await;
+
+        assert_eq!(spool.replay(&conn).await.unwrap(), 1);
+        assert_eq!(stored(&conn).await, ["run-1"]);
+        assert_eq!(names(&spool), ["0000000000000-garbage.corrupt"]);
+        assert_eq!(spool.backlog().unwrap(), (0, 0));
+        fs::remove_dir_all(&spool.dir).unwrap();
+    }
+}
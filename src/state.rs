@@ -0,0 +1,17 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Directory for files the agent must keep between runs, when neither the
+/// config file nor `TCL_STATE_DIR` names one.
+///
+/// Uses `$XDG_STATE_HOME/tcl`, then `$HOME/.local/state/tcl`, and finally
+/// `/var/lib/tcl`.
+pub fn default_state_dir() -> PathBuf {
+    if let Ok(dir) = env::var("XDG_STATE_HOME") {
+        return PathBuf::from(dir).join("tcl");
+    }
+    if let Ok(home) = env::var("HOME") {
+        return PathBuf::from(home).join(".local/state/tcl");
+    }
+    PathBuf::from("/var/lib/tcl")
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+/// Parses a duration such as `90s`, `15m`, `6h`, `7d` or `2w`.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let invalid = || {
+        format!(
+            "invalid duration `{}` (expected e.g. 30s, 15m, 6h, 7d)",
+            text
+        )
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in `{}` (expected s, m, h, d or w)",
+                text
+            ))
+        }
+    };
+    number
+        .checked_mul(secs)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// Parses a point in time as UTC milliseconds since the Unix epoch.
+///
+/// Accepts `now`, a duration before `now` such as `1h`, or a UTC date and
+/// optional time: `2024-06-01`, `2024-06-01T12:30`, `2024-06-01 12:30:15Z`.
+pub fn parse_time(text: &str, now: i64) -> Result<i64, String> {
+    let text = text.trim();
+    let invalid = || {
+        format!(
+            "invalid time `{}` (expected e.g. 2024-06-01, 2024-06-01T12:30Z or 1h)",
+            text
+        )
+    };
+    if text == "now" {
+        return Ok(now);
+    }
+    if let Ok(ago) = parse_duration(text) {
+        return i64::try_from(ago.as_millis())
+            .ok()
+            .and_then(|ago| now.checked_sub(ago))
+            .ok_or_else(invalid);
+    }
+
+    let text = text.strip_suffix('Z').unwrap_or(text);
+    let (date, time) = match text.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let mut date_parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) =
+        (date_parts.next(), date_parts.next(), date_parts.next())
+    else {
+        return Err(invalid());
+    };
+    // Four-digit years, as `format_millis` writes them.
+    if !(0..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0;
+    if let Some(time) = time {
+        let parts: Vec<Option<i64>> = time.split(':').map(|p| p.parse().ok()).collect();
+        let (hour, minute, second) = match parts[..] {
+            [Some(h), Some(m)] => (h, m, 0),
+            [Some(h), Some(m), Some(s)] => (h, m, s),
+            _ => return Err(invalid()),
+        };
+        if hour > 23 || minute > 59 || second > 60 {
+            return Err(invalid());
+        }
+        seconds = hour * 3600 + minute * 60 + second;
+    }
+
+    days_from_civil(year, month, day)
+        .checked_mul(86_400)
+        .and_then(|secs| secs.checked_add(seconds))
+        .and_then(|secs| secs.checked_mul(1000))
+        .ok_or_else(invalid)
+}
+
+/// Formats UTC milliseconds since the Unix epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn format_millis(millis: i64) -> String {
+    let secs = millis.div_euclid(1000);
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's algorithm).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_717_245_000_000; // 2024-06-01T12:30:00Z
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(900)));
+        assert_eq!(parse_duration("6h"), Ok(Duration::from_secs(21_600)));
+        assert_eq!(parse_duration(" 7d "), Ok(Duration::from_secs(604_800)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(1_209_600)));
+        assert_eq!(parse_duration("0s"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for text in ["", "5", "s", "5y", "-5s", "1.5h", "99999999999999999w"] {
+            assert!(parse_duration(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("now", NOW), Ok(NOW));
+        assert_eq!(parse_time("1h", NOW), Ok(NOW - 3_600_000));
+        assert_eq!(parse_time("2024-06-01", NOW), Ok(1_717_200_000_000));
+        assert_eq!(parse_time("2024-06-01T12:30", NOW), Ok(NOW));
+        assert_eq!(parse_time("2024-06-01 12:30:15Z", NOW), Ok(NOW + 15_000));
+        assert_eq!(parse_time("1970-01-01", NOW), Ok(0));
+        assert_eq!(parse_time("1969-12-31T23:59:59Z", NOW), Ok(-1000));
+    }
+
+    #[test]
+    fn rejects_bad_times() {
+        for text in [
+            "",
+            "yesterday",
+            "2024-13-01",
+            "2024-06-32",
+            "2024-06",
+            "2024-06-01T24:00",
+            "2024-06-01T12",
+            "9999999999-01-01",
+            "99999999999999999w",
+            "9999999999999999w",
+        ] {
+            assert!(parse_time(text, NOW).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn formats_millis() {
+        assert_eq!(format_millis(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_millis(NOW + 999), "2024-06-01T12:30:00Z");
+        assert_eq!(format_millis(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn format_and_parse_round_trip() {
+        for text in [
+            "1970-01-01T00:00:00Z",
+            "2000-02-29T23:59:59Z",
+            "2024-02-29T00:00:00Z",
+            "2100-03-01T06:07:08Z",
+            "1900-03-01T12:00:00Z",
+            "0000-01-01T00:00:00Z",
+            "9999-12-31T23:59:59Z",
+        ] {
+            let millis = parse_time(text, NOW).unwrap();
+            assert_eq!(format_millis(millis), text);
+            assert_eq!(parse_time(&format_millis(millis), NOW), Ok(millis));
+        }
+    }
+}